@@ -0,0 +1,330 @@
+use seed::{prelude::*, *};
+use std::borrow::Cow;
+
+pub const TITLE: &str = "Login";
+pub const DESCRIPTION: &str =
+    "Sign up or log in with a username and password, or exchange an API key for a bearer token.
+    Credentials are stored in the browser and attached to all requests to the server.";
+
+const TOKEN_STORAGE_KEY: &str = "access_token";
+const CSRF_STORAGE_KEY: &str = "csrf_token";
+const CSRF_HEADER: &str = "x-csrf-token";
+
+const TOKEN_URL: &str = "/auth/token";
+const REGISTER_URL: &str = "/auth/register";
+const LOGIN_URL: &str = "/auth/login";
+const LOGOUT_URL: &str = "/auth/logout";
+const SESSION_URL: &str = "/auth/session";
+const HISTORY_URL: &str = "/api/messages";
+
+// ------ ------
+//  Credentials
+// ------ ------
+
+fn stored(key: &str) -> Option<String> {
+    LocalStorage::get(key).ok()
+}
+
+/// `Request::new` with the stored bearer token and CSRF token.
+/// The session cookie is sent by the browser.
+pub fn request<'a>(url: impl Into<Cow<'a, str>>) -> Request<'a> {
+    let mut request = Request::new(url);
+    if let Some(token) = stored(TOKEN_STORAGE_KEY) {
+        request = request.header(Header::bearer(token));
+    }
+    if let Some(csrf_token) = stored(CSRF_STORAGE_KEY) {
+        request = request.header(Header::custom(CSRF_HEADER, csrf_token));
+    }
+    request
+}
+
+/// Adds the stored token as a query parameter,
+/// WebSocket and EventSource requests can't have custom headers.
+pub fn with_access_token(url: String) -> String {
+    match stored(TOKEN_STORAGE_KEY) {
+        Some(token) => {
+            let separator = if url.contains('?') { '&' } else { '?' };
+            format!("{}{}access_token={}", url, separator, token)
+        }
+        None => url,
+    }
+}
+
+// ------ ------
+//     Init
+// ------ ------
+
+pub fn init(orders: &mut impl Orders<Msg>) -> Model {
+    orders.perform_cmd(async { Msg::SessionFetched(fetch_session().await) });
+    Model {
+        username: String::new(),
+        password: String::new(),
+        api_key: String::new(),
+        login: if stored(TOKEN_STORAGE_KEY).is_some() {
+            Login::ApiKey
+        } else {
+            Login::LoggedOut
+        },
+        history: None,
+        error: None,
+    }
+}
+
+// ------ ------
+//     Model
+// ------ ------
+
+pub struct Model {
+    username: String,
+    password: String,
+    api_key: String,
+    login: Login,
+    history: Option<Vec<shared::SendMessageResponseBody>>,
+    error: Option<String>,
+}
+
+enum Login {
+    LoggedOut,
+    User(String),
+    ApiKey,
+}
+
+// ------ ------
+//    Update
+// ------ ------
+
+pub enum Msg {
+    UsernameChanged(String),
+    PasswordChanged(String),
+    ApiKeyChanged(String),
+    SignUp,
+    LogIn,
+    LogInWithApiKey,
+    LogOut,
+    SessionFetched(fetch::Result<shared::SessionResponseBody>),
+    LoggedIn(fetch::Result<shared::SessionResponseBody>),
+    TokenFetched(fetch::Result<shared::TokenResponseBody>),
+    LoggedOut,
+    LoadHistory,
+    HistoryFetched(fetch::Result<Vec<shared::SendMessageResponseBody>>),
+}
+
+pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
+    match msg {
+        Msg::UsernameChanged(username) => {
+            model.username = username;
+        }
+        Msg::PasswordChanged(password) => {
+            model.password = password;
+        }
+        Msg::ApiKeyChanged(api_key) => {
+            model.api_key = api_key;
+        }
+        Msg::SignUp | Msg::LogIn => {
+            let url = if let Msg::SignUp = msg {
+                REGISTER_URL
+            } else {
+                LOGIN_URL
+            };
+            let credentials = shared::CredentialsRequestBody {
+                username: model.username.clone(),
+                password: model.password.clone(),
+            };
+            orders.skip().perform_cmd(async move {
+                Msg::LoggedIn(send_credentials(url, credentials).await)
+            });
+        }
+        Msg::LogInWithApiKey => {
+            orders.skip().perform_cmd({
+                let api_key = model.api_key.clone();
+                async { Msg::TokenFetched(fetch_token(api_key).await) }
+            });
+        }
+        Msg::LogOut => match model.login {
+            Login::User(_) => {
+                orders.perform_cmd(async {
+                    if let Err(fetch_error) = log_out().await {
+                        log!("Logout error:", fetch_error);
+                    }
+                    Msg::LoggedOut
+                });
+            }
+            Login::ApiKey => {
+                LocalStorage::remove(TOKEN_STORAGE_KEY).expect("remove token from LocalStorage");
+                reload();
+            }
+            Login::LoggedOut => (),
+        },
+        Msg::SessionFetched(Ok(session)) | Msg::LoggedIn(Ok(session)) => {
+            LocalStorage::insert(CSRF_STORAGE_KEY, &session.csrf_token)
+                .expect("save CSRF token to LocalStorage");
+            model.login = Login::User(session.username);
+            model.password.clear();
+            model.error = None;
+        }
+        // No session cookie or the session expired.
+        Msg::SessionFetched(Err(_)) => {
+            let _ = LocalStorage::remove(CSRF_STORAGE_KEY);
+        }
+        Msg::LoggedIn(Err(fetch_error)) => {
+            log!("Login error:", fetch_error);
+            model.error = Some(
+                "Login failed. Usernames have 3 to 32 letters, digits, '-', '_' or '.', \
+                passwords at least 8 characters."
+                    .to_owned(),
+            );
+        }
+        Msg::TokenFetched(Ok(response_data)) => {
+            LocalStorage::insert(TOKEN_STORAGE_KEY, &response_data.token)
+                .expect("save token to LocalStorage");
+            reload();
+        }
+        Msg::TokenFetched(Err(fetch_error)) => {
+            log!("Login error:", fetch_error);
+            model.error = Some("Login failed, check the API key.".to_owned());
+        }
+        Msg::LoggedOut => {
+            let _ = LocalStorage::remove(CSRF_STORAGE_KEY);
+            model.login = Login::LoggedOut;
+            model.history = None;
+        }
+        Msg::LoadHistory => {
+            orders
+                .skip()
+                .perform_cmd(async { Msg::HistoryFetched(fetch_history().await) });
+        }
+        Msg::HistoryFetched(Ok(history)) => {
+            model.history = Some(history);
+        }
+        Msg::HistoryFetched(Err(fetch_error)) => {
+            log!("History error:", fetch_error);
+            orders.skip();
+        }
+    }
+}
+
+async fn send_credentials(
+    url: &'static str,
+    credentials: shared::CredentialsRequestBody,
+) -> fetch::Result<shared::SessionResponseBody> {
+    Request::new(url)
+        .method(Method::Post)
+        .json(&credentials)?
+        .fetch()
+        .await?
+        .check_status()?
+        .json()
+        .await
+}
+
+async fn fetch_session() -> fetch::Result<shared::SessionResponseBody> {
+    Request::new(SESSION_URL)
+        .fetch()
+        .await?
+        .check_status()?
+        .json()
+        .await
+}
+
+async fn log_out() -> fetch::Result<()> {
+    request(LOGOUT_URL)
+        .method(Method::Post)
+        .fetch()
+        .await?
+        .check_status()
+        .map(|_| ())
+}
+
+async fn fetch_token(api_key: String) -> fetch::Result<shared::TokenResponseBody> {
+    Request::new(TOKEN_URL)
+        .method(Method::Post)
+        .json(&shared::TokenRequestBody { api_key })?
+        .fetch()
+        .await?
+        .check_status()?
+        .json()
+        .await
+}
+
+async fn fetch_history() -> fetch::Result<Vec<shared::SendMessageResponseBody>> {
+    request(HISTORY_URL)
+        .fetch()
+        .await?
+        .check_status()?
+        .json()
+        .await
+}
+
+// Open WebSockets and EventSources have to reconnect with the new token.
+fn reload() {
+    window().location().reload().expect("reload page");
+}
+
+// ------ ------
+//     View
+// ------ ------
+
+pub fn view(model: &Model, intro: impl FnOnce(&str, &str) -> Vec<Node<Msg>>) -> Vec<Node<Msg>> {
+    match &model.login {
+        Login::User(username) => nodes![
+            intro(TITLE, DESCRIPTION),
+            div![format!("Logged in as {}.", username)],
+            button![ev(Ev::Click, |_| Msg::LogOut), "Log out"],
+            button![ev(Ev::Click, |_| Msg::LoadHistory), "Show my messages"],
+            view_history(&model.history),
+        ],
+        Login::ApiKey => nodes![
+            intro(TITLE, DESCRIPTION),
+            div!["Logged in with an API key."],
+            button![ev(Ev::Click, |_| Msg::LogOut), "Log out"],
+        ],
+        Login::LoggedOut => nodes![
+            intro(TITLE, DESCRIPTION),
+            div![
+                input![
+                    input_ev(Ev::Input, Msg::UsernameChanged),
+                    attrs! {
+                        At::Placeholder => "Username",
+                        At::Value => model.username,
+                    }
+                ],
+                input![
+                    input_ev(Ev::Input, Msg::PasswordChanged),
+                    attrs! {
+                        At::Type => "password",
+                        At::Placeholder => "Password",
+                        At::Value => model.password,
+                    }
+                ],
+                button![ev(Ev::Click, |_| Msg::SignUp), "Sign up"],
+                button![ev(Ev::Click, |_| Msg::LogIn), "Log in"],
+            ],
+            div![
+                input![
+                    input_ev(Ev::Input, Msg::ApiKeyChanged),
+                    attrs! {
+                        At::Type => "password",
+                        At::Placeholder => "API key",
+                        At::Value => model.api_key,
+                    }
+                ],
+                button![
+                    ev(Ev::Click, |_| Msg::LogInWithApiKey),
+                    "Log in with API key"
+                ],
+            ],
+            model.error.as_ref().map(|error| div![error]),
+        ],
+    }
+}
+
+fn view_history(history: &Option<Vec<shared::SendMessageResponseBody>>) -> Node<Msg> {
+    match history {
+        Some(history) if history.is_empty() => div!["No messages yet."],
+        Some(history) => ol![history.iter().map(|message| li![format!(
+            "{}. message: \"{}\"",
+            message.ordinal_number, message.text
+        )])],
+        None => empty![],
+    }
+}
@@ -0,0 +1,257 @@
+use seed::{prelude::*, *};
+use web_sys::{EventSource, MessageEvent};
+
+use crate::{auth, download};
+
+pub const TITLE: &str = "Batch conversion";
+pub const DESCRIPTION: &str =
+    "Click 'Convert batch' to let the server convert a full turn of rotation matrices
+    into quaternions. The conversion is slowed down, progress is streamed back as Server-Sent Events.
+    The results can be downloaded as CSV, JSON or NumPy files.";
+
+const BATCH_DURATION_MS: u32 = 3000;
+const BATCH_SIZE: u32 = 36;
+
+const EXPORT_FORMATS: &[&str] = &["csv", "json", "npy"];
+const EXPORT_DATA: &[(&str, &str)] = &[
+    ("quaternions", "Quaternions"),
+    ("matrices", "Rotation matrices"),
+    ("euler_angles", "Euler angles"),
+];
+
+fn get_event_source_url() -> String {
+    auth::with_access_token(format!(
+        "/api/batch-conversion/{}?count={}",
+        BATCH_DURATION_MS, BATCH_SIZE
+    ))
+}
+
+fn get_export_url(export: &Export) -> String {
+    format!(
+        "/api/batch-conversion/export?format={}&data={}",
+        export.format, export.data
+    )
+}
+
+fn get_export_file_name(export: &Export) -> String {
+    format!("{}.{}", export.data, export.format)
+}
+
+// ------ ------
+//     Model
+// ------ ------
+
+#[derive(Default)]
+pub struct Model {
+    subscription: Option<Subscription>,
+    progress: Option<shared::ConversionProgress>,
+    failed: bool,
+    export: Export,
+}
+
+struct Export {
+    format: &'static str,
+    data: &'static str,
+}
+
+impl Default for Export {
+    fn default() -> Self {
+        Self {
+            format: EXPORT_FORMATS[0],
+            data: EXPORT_DATA[0].0,
+        }
+    }
+}
+
+// `EventSource` stays open as long as its callbacks are alive.
+struct Subscription {
+    event_source: EventSource,
+    _on_message: Closure<dyn Fn(MessageEvent)>,
+    _on_error: Closure<dyn Fn(JsValue)>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.event_source.close();
+    }
+}
+
+// ------ ------
+//    Update
+// ------ ------
+
+pub enum Msg {
+    StartConversion,
+    ProgressReceived(shared::ConversionProgress),
+    ConversionFailed,
+    ExportFormatChanged(String),
+    ExportDataChanged(String),
+    ExportRequested,
+    ExportFetched(String, fetch::Result<Vec<u8>>),
+}
+
+pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
+    match msg {
+        Msg::StartConversion => {
+            model.progress = None;
+            model.failed = false;
+            model.subscription = match subscribe(orders) {
+                Ok(subscription) => Some(subscription),
+                Err(error) => {
+                    log!("Batch conversion error:", error);
+                    model.failed = true;
+                    None
+                }
+            };
+        }
+        Msg::ProgressReceived(progress) => {
+            if let shared::ConversionProgress::Completed { .. } = progress {
+                model.subscription = None;
+            }
+            model.progress = Some(progress);
+        }
+        Msg::ConversionFailed => {
+            model.subscription = None;
+            model.failed = true;
+        }
+        Msg::ExportFormatChanged(format) => {
+            if let Some(format) = EXPORT_FORMATS.iter().find(|known| **known == format) {
+                model.export.format = *format;
+            }
+        }
+        Msg::ExportDataChanged(data) => {
+            if let Some((data, _)) = EXPORT_DATA.iter().find(|(known, _)| *known == data) {
+                model.export.data = *data;
+            }
+        }
+        Msg::ExportRequested => {
+            if let Some(shared::ConversionProgress::Completed { quaternions }) = &model.progress {
+                let url = get_export_url(&model.export);
+                let file_name = get_export_file_name(&model.export);
+                let quaternions = quaternions.clone();
+                orders.perform_cmd(async move {
+                    Msg::ExportFetched(file_name, fetch_export(url, quaternions).await)
+                });
+            }
+        }
+        Msg::ExportFetched(file_name, Ok(bytes)) => {
+            if let Err(error) = download::save_file(&bytes, &file_name) {
+                error!("Saving the export failed!", error);
+            }
+        }
+        Msg::ExportFetched(_, Err(fetch_error)) => {
+            error!("Export failed!", fetch_error);
+        }
+    }
+}
+
+// The server exports the quaternions we send, a link couldn't send the bearer token header.
+async fn fetch_export(url: String, quaternions: Vec<shared::Quaternion>) -> fetch::Result<Vec<u8>> {
+    auth::request(url)
+        .method(fetch::Method::Post)
+        .json(&quaternions)?
+        .fetch()
+        .await?
+        .check_status()?
+        .bytes()
+        .await
+}
+
+fn subscribe(orders: &impl Orders<Msg>) -> Result<Subscription, JsValue> {
+    let event_source = EventSource::new(&get_event_source_url())?;
+
+    let msg_sender = orders.msg_sender();
+    let on_message = Closure::wrap(Box::new(move |event: MessageEvent| {
+        let progress = event
+            .data()
+            .as_string()
+            .and_then(|data| serde_json::from_str(&data).ok());
+        msg_sender(Some(match progress {
+            Some(progress) => Msg::ProgressReceived(progress),
+            None => Msg::ConversionFailed,
+        }));
+    }) as Box<dyn Fn(MessageEvent)>);
+    event_source.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+    // `EventSource` reconnects on its own, we don't want to restart the batch.
+    let msg_sender = orders.msg_sender();
+    let on_error = Closure::wrap(Box::new(move |_| {
+        msg_sender(Some(Msg::ConversionFailed));
+    }) as Box<dyn Fn(JsValue)>);
+    event_source.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+
+    Ok(Subscription {
+        event_source,
+        _on_message: on_message,
+        _on_error: on_error,
+    })
+}
+
+// ------ ------
+//     View
+// ------ ------
+
+pub fn view(model: &Model, intro: impl FnOnce(&str, &str) -> Vec<Node<Msg>>) -> Vec<Node<Msg>> {
+    nodes![
+        intro(TITLE, DESCRIPTION),
+        view_progress(&model.progress),
+        IF!(matches!(model.progress, Some(shared::ConversionProgress::Completed { .. })) => {
+            view_export(&model.export)
+        }),
+        IF!(model.failed => div!["Conversion failed."]),
+        button![
+            attrs! {At::Disabled => model.subscription.is_some().as_at_value()},
+            ev(Ev::Click, |_| Msg::StartConversion),
+            "Convert batch"
+        ],
+    ]
+}
+
+fn view_progress(progress: &Option<shared::ConversionProgress>) -> Node<Msg> {
+    match progress {
+        None => empty![],
+        Some(shared::ConversionProgress::Started { total }) => {
+            div![format!("Converting {} matrices...", total)]
+        }
+        Some(shared::ConversionProgress::Progress {
+            done,
+            total,
+            percent,
+        }) => div![
+            progress![attrs! {At::Value => percent, At::Max => 100}],
+            format!(" {}/{}", done, total),
+        ],
+        Some(shared::ConversionProgress::Completed { quaternions }) => {
+            ol![quaternions.iter().map(|q| li![format!(
+                "x: {:.2}, y: {:.2}, z: {:.2}, w: {:.2}",
+                q.x, q.y, q.z, q.w
+            )])]
+        }
+    }
+}
+
+fn view_export(export: &Export) -> Node<Msg> {
+    div![
+        select![
+            input_ev(Ev::Change, Msg::ExportDataChanged),
+            EXPORT_DATA.iter().map(|(data, label)| option![
+                attrs! {
+                    At::Value => data,
+                    At::Selected => (*data == export.data).as_at_value(),
+                },
+                *label,
+            ]),
+        ],
+        select![
+            input_ev(Ev::Change, Msg::ExportFormatChanged),
+            EXPORT_FORMATS.iter().map(|format| option![
+                attrs! {
+                    At::Value => format,
+                    At::Selected => (*format == export.format).as_at_value(),
+                },
+                *format,
+            ]),
+        ],
+        button![ev(Ev::Click, |_| Msg::ExportRequested), "Download"],
+    ]
+}
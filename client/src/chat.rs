@@ -0,0 +1,133 @@
+use seed::{prelude::*, *};
+
+use crate::auth;
+
+pub const TITLE: &str = "Chat";
+pub const DESCRIPTION: &str =
+    "Messages sent here or through Example A are broadcast to all connected clients
+    with their ordinal number.";
+
+fn get_web_socket_url() -> String {
+    let location = window().location();
+    let protocol = match location.protocol() {
+        Ok(protocol) if protocol == "https:" => "wss",
+        _ => "ws",
+    };
+    let host = location.host().expect("get location host");
+    auth::with_access_token(format!("{}://{}/api/chat", protocol, host))
+}
+
+// ------ ------
+//     Init
+// ------ ------
+
+pub fn init(orders: &mut impl Orders<Msg>) -> Model {
+    Model {
+        web_socket: create_web_socket(orders),
+        web_socket_reconnector: None,
+        connected: false,
+        new_message: String::new(),
+        messages: Vec::new(),
+    }
+}
+
+// ------ ------
+//     Model
+// ------ ------
+
+pub struct Model {
+    web_socket: WebSocket,
+    web_socket_reconnector: Option<StreamHandle>,
+    connected: bool,
+    new_message: String,
+    messages: Vec<shared::SendMessageResponseBody>,
+}
+
+// ------ ------
+//    Update
+// ------ ------
+
+pub enum Msg {
+    WebSocketOpened,
+    WebSocketClosed(CloseEvent),
+    WebSocketFailed,
+    ReconnectWebSocket(usize),
+    MessageReceived(WebSocketMessage),
+    NewMessageChanged(String),
+    SendMessage,
+}
+
+pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
+    match msg {
+        Msg::WebSocketOpened => {
+            model.connected = true;
+            model.web_socket_reconnector = None;
+        }
+        Msg::WebSocketClosed(_) | Msg::WebSocketFailed => {
+            model.connected = false;
+            if model.web_socket_reconnector.is_none() {
+                model.web_socket_reconnector = Some(
+                    orders.stream_with_handle(streams::backoff(None, Msg::ReconnectWebSocket)),
+                );
+            }
+        }
+        Msg::ReconnectWebSocket(retries) => {
+            log!("Chat reconnect attempt:", retries);
+            model.web_socket = create_web_socket(orders);
+        }
+        Msg::MessageReceived(message) => match message.json() {
+            Ok(message) => model.messages.push(message),
+            Err(error) => {
+                log!("Chat error:", error);
+                orders.skip();
+            }
+        },
+        Msg::NewMessageChanged(message) => {
+            model.new_message = message;
+        }
+        Msg::SendMessage => {
+            let request_data = shared::SendMessageRequestBody {
+                text: std::mem::take(&mut model.new_message),
+            };
+            if let Err(error) = model.web_socket.send_json(&request_data) {
+                log!("Chat error:", error);
+            }
+        }
+    }
+}
+
+fn create_web_socket(orders: &impl Orders<Msg>) -> WebSocket {
+    WebSocket::builder(get_web_socket_url(), orders)
+        .on_open(|| Msg::WebSocketOpened)
+        .on_message(Msg::MessageReceived)
+        .on_close(Msg::WebSocketClosed)
+        .on_error(|| Msg::WebSocketFailed)
+        .build_and_open()
+        .expect("open WebSocket")
+}
+
+// ------ ------
+//     View
+// ------ ------
+
+pub fn view(model: &Model, intro: impl FnOnce(&str, &str) -> Vec<Node<Msg>>) -> Vec<Node<Msg>> {
+    nodes![
+        intro(TITLE, DESCRIPTION),
+        IF!(not(model.connected) => div!["Connecting..."]),
+        ul![model.messages.iter().map(|message| li![format!(
+            r#"{}. message: "{}""#,
+            message.ordinal_number, message.text
+        )])],
+        input![
+            input_ev(Ev::Input, Msg::NewMessageChanged),
+            attrs! {
+                At::Value => model.new_message,
+            }
+        ],
+        button![
+            attrs! {At::Disabled => not(model.connected).as_at_value()},
+            ev(Ev::Click, |_| Msg::SendMessage),
+            "Send message"
+        ],
+    ]
+}
@@ -0,0 +1,15 @@
+use seed::{document, prelude::*};
+use web_sys::{Blob, HtmlElement, Url};
+
+/// Lets the browser save `bytes` as `file_name` through a temporary object URL.
+/// Downloads of protected files are fetched with the bearer token header instead of a link.
+pub fn save_file(bytes: &[u8], file_name: &str) -> Result<(), JsValue> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let blob = Blob::new_with_u8_array_sequence(&parts)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+    let link = document().create_element("a")?;
+    link.set_attribute("href", &url)?;
+    link.set_attribute("download", file_name)?;
+    link.unchecked_ref::<HtmlElement>().click();
+    Url::revoke_object_url(&url)
+}
@@ -1,18 +1,23 @@
 use seed::{prelude::*, *};
 use std::borrow::Cow;
 use std::mem;
-use web_sys::{
-    self,
-    console::{log_1, log_2},
-    File, FormData,
-};
+use web_sys::{self, File, FormData};
+
+use crate::{auth, download};
 
 pub const TITLE: &str = "Example E";
 pub const DESCRIPTION: &str =
-    "Fill form and click 'Submit` button. Server echoes the form back. See console log for more info.";
+    "Fill form and click 'Submit` button. Server echoes the form back and stores the file.";
+
+const FILES_URL: &str = "/api/files";
 
+// `answer` is echoed as a boolean, all other text fields as text.
 fn get_request_url() -> impl Into<Cow<'static, str>> {
-    "/api/form"
+    "/api/form?checkboxes=answer"
+}
+
+fn file_url(id: &str) -> String {
+    format!("{}/{}", FILES_URL, id)
 }
 
 // ------ ------
@@ -40,31 +45,41 @@ impl Form {
     }
 }
 
-pub enum Model {
+pub enum Submission {
     ReadyToSubmit(Form),
     WaitingForResponse(Form),
 }
 
+pub struct Model {
+    submission: Submission,
+    echo: Option<shared::FormSubmission>,
+    files: Vec<shared::StoredFile>,
+}
+
 impl Default for Model {
     fn default() -> Self {
-        Self::ReadyToSubmit(Form {
-            title: "I'm title".into(),
-            description: "I'm description".into(),
-            file: None,
-            answer: true,
-        })
+        Self {
+            submission: Submission::ReadyToSubmit(Form {
+                title: "I'm title".into(),
+                description: "I'm description".into(),
+                file: None,
+                answer: true,
+            }),
+            echo: None,
+            files: Vec::new(),
+        }
     }
 }
 
 impl Model {
     const fn form(&self) -> &Form {
-        match self {
-            Self::ReadyToSubmit(form) | Self::WaitingForResponse(form) => form,
+        match &self.submission {
+            Submission::ReadyToSubmit(form) | Submission::WaitingForResponse(form) => form,
         }
     }
     fn form_mut(&mut self) -> &mut Form {
-        match self {
-            Self::ReadyToSubmit(form) | Self::WaitingForResponse(form) => form,
+        match &mut self.submission {
+            Submission::ReadyToSubmit(form) | Submission::WaitingForResponse(form) => form,
         }
     }
 }
@@ -79,7 +94,13 @@ pub enum Msg {
     FileChanged(Option<File>),
     AnswerChanged,
     FormSubmitted(String),
-    ServerResponded(fetch::Result<String>),
+    ServerResponded(fetch::Result<shared::FormSubmission>),
+    FetchFiles,
+    FilesFetched(fetch::Result<Vec<shared::StoredFile>>),
+    DownloadFile(shared::StoredFile),
+    FileDownloaded(String, fetch::Result<Vec<u8>>),
+    DeleteFile(String),
+    FileDeleted(fetch::Result<()>),
 }
 
 pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
@@ -94,35 +115,99 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
             let form = mem::take(model.form_mut());
             let form_data = form.to_form_data().expect("create from data from form");
             orders.perform_cmd(async { Msg::ServerResponded(send_request(form_data).await) });
-            *model = Model::WaitingForResponse(form);
+            model.submission = Submission::WaitingForResponse(form);
             log!(format!("Form {} submitted.", id));
         }
         Msg::ServerResponded(Ok(response_data)) => {
-            *model = Model::ReadyToSubmit(Form::default());
+            model.submission = Submission::ReadyToSubmit(Form::default());
+            model.echo = Some(response_data);
             clear_file_input();
-            log_2(
-                &"%cResponse data:".into(),
-                &"background: yellow; color: black".into(),
-            );
-            log_1(&response_data.into());
+            orders.send_msg(Msg::FetchFiles);
         }
         Msg::ServerResponded(Err(fetch_error)) => {
-            *model = Model::ReadyToSubmit(mem::take(model.form_mut()));
+            model.submission = Submission::ReadyToSubmit(mem::take(model.form_mut()));
             error!("Request failed!", fetch_error);
         }
+        Msg::FetchFiles => {
+            orders
+                .skip()
+                .perform_cmd(async { Msg::FilesFetched(fetch_files().await) });
+        }
+        Msg::FilesFetched(Ok(files)) => {
+            model.files = files;
+        }
+        Msg::FilesFetched(Err(fetch_error)) => {
+            error!("Fetching files failed!", fetch_error);
+            orders.skip();
+        }
+        Msg::DownloadFile(file) => {
+            orders.skip().perform_cmd(async move {
+                Msg::FileDownloaded(file.name, download_file(&file.id).await)
+            });
+        }
+        Msg::FileDownloaded(name, Ok(bytes)) => {
+            if let Err(error) = download::save_file(&bytes, &name) {
+                error!("Saving the file failed!", error);
+            }
+            orders.skip();
+        }
+        Msg::FileDownloaded(_, Err(fetch_error)) => {
+            error!("Downloading the file failed!", fetch_error);
+            orders.skip();
+        }
+        Msg::DeleteFile(id) => {
+            orders
+                .skip()
+                .perform_cmd(async move { Msg::FileDeleted(delete_file(&id).await) });
+        }
+        Msg::FileDeleted(result) => {
+            if let Err(fetch_error) = result {
+                error!("Deleting the file failed!", fetch_error);
+            }
+            orders.skip().send_msg(Msg::FetchFiles);
+        }
     }
 }
 
-async fn send_request(form: FormData) -> fetch::Result<String> {
-    Request::new(get_request_url())
+async fn send_request(form: FormData) -> fetch::Result<shared::FormSubmission> {
+    auth::request(get_request_url())
         .method(fetch::Method::Post)
         .body(form.into())
         .fetch()
         .await?
-        .text()
+        .check_status()?
+        .json()
         .await
 }
 
+async fn fetch_files() -> fetch::Result<Vec<shared::StoredFile>> {
+    auth::request(FILES_URL)
+        .fetch()
+        .await?
+        .check_status()?
+        .json()
+        .await
+}
+
+// Fetched with the bearer token header, a link couldn't send it.
+async fn download_file(id: &str) -> fetch::Result<Vec<u8>> {
+    auth::request(file_url(id))
+        .fetch()
+        .await?
+        .check_status()?
+        .bytes()
+        .await
+}
+
+async fn delete_file(id: &str) -> fetch::Result<()> {
+    auth::request(file_url(id))
+        .method(fetch::Method::Delete)
+        .fetch()
+        .await?
+        .check_status()
+        .map(|_| ())
+}
+
 #[allow(clippy::option_map_unit_fn)]
 fn clear_file_input() {
     seed::document()
@@ -155,7 +240,8 @@ fn view_form_field(mut label: Node<Msg>, control: Node<Msg>) -> Node<Msg> {
 }
 
 pub fn view(model: &Model, intro: impl FnOnce(&str, &str) -> Vec<Node<Msg>>) -> Vec<Node<Msg>> {
-    let btn_enabled = matches!(model, Model::ReadyToSubmit(form) if !form.title.is_empty());
+    let btn_enabled =
+        matches!(&model.submission, Submission::ReadyToSubmit(form) if !form.title.is_empty());
 
     let form_id = "A_FORM".to_string();
     let form = form![
@@ -229,5 +315,50 @@ pub fn view(model: &Model, intro: impl FnOnce(&str, &str) -> Vec<Node<Msg>>) ->
         ]
     ];
 
-    nodes![intro(TITLE, DESCRIPTION), form]
+    nodes![
+        intro(TITLE, DESCRIPTION),
+        form,
+        model.echo.as_ref().map(view_echo),
+        view_files(&model.files),
+    ]
+}
+
+fn view_echo(submission: &shared::FormSubmission) -> Node<Msg> {
+    table![submission.fields.iter().map(|field| {
+        let value = match &field.value {
+            shared::FormValue::Text(text) => td![text],
+            shared::FormValue::Bool(boolean) => td![if *boolean { "yes" } else { "no" }],
+            shared::FormValue::File(file) => td![
+                view_file_link(file),
+                format!(" ({}, {} bytes)", file.content_type, file.size),
+            ],
+        };
+        tr![th![&field.name], value]
+    })]
+}
+
+fn view_files(files: &[shared::StoredFile]) -> Node<Msg> {
+    div![
+        button![ev(Ev::Click, |_| Msg::FetchFiles), "Show uploaded files"],
+        ul![files.iter().map(|file| {
+            let id = file.id.clone();
+            li![
+                view_file_link(file),
+                format!(" ({}, {} bytes) ", file.content_type, file.size),
+                button![ev(Ev::Click, move |_| Msg::DeleteFile(id)), "Delete"],
+            ]
+        })],
+    ]
+}
+
+fn view_file_link(file: &shared::StoredFile) -> Node<Msg> {
+    let download = file.clone();
+    a![
+        attrs! {At::Href => file_url(&file.id)},
+        &file.name,
+        ev(Ev::Click, move |event| {
+            event.prevent_default();
+            Msg::DownloadFile(download)
+        }),
+    ]
 }
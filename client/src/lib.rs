@@ -2,32 +2,44 @@
 
 use seed::{prelude::*, *};
 
+mod auth;
+mod batch_conversion;
+mod chat;
+mod download;
 //mod example_a;
 //mod example_b;
 //mod example_c;
 //mod example_d;
-//mod example_e;
+mod example_e;
 mod matrix_form;
 
 // ------ ------
 //     Init
 // ------ ------
 
-fn init(_: Url, _: &mut impl Orders<Msg>) -> Model {
-    Model::default()
+fn init(_: Url, orders: &mut impl Orders<Msg>) -> Model {
+    Model {
+        auth: auth::init(&mut orders.proxy(Msg::Auth)),
+        batch_conversion: batch_conversion::Model::default(),
+        chat: chat::init(&mut orders.proxy(Msg::Chat)),
+        example_e: example_e::Model::default(),
+        matrix_form: matrix_form::Model::default(),
+    }
 }
 
 // ------ ------
 //     Model
 // ------ ------
 
-#[derive(Default)]
 struct Model {
+    auth: auth::Model,
+    batch_conversion: batch_conversion::Model,
+    chat: chat::Model,
     //example_a: example_a::Model,
     //example_b: example_b::Model,
     //example_c: example_c::Model,
     //example_d: example_d::Model,
-    //example_e: example_e::Model,
+    example_e: example_e::Model,
     matrix_form: matrix_form::Model,
 }
 
@@ -36,16 +48,32 @@ struct Model {
 // ------ ------
 
 enum Msg {
+    Auth(auth::Msg),
+    BatchConversion(batch_conversion::Msg),
+    Chat(chat::Msg),
     //ExampleA(example_a::Msg),
     //ExampleB(example_b::Msg),
     //ExampleC(example_c::Msg),
     //ExampleD(example_d::Msg),
-    //ExampleE(example_e::Msg),
+    ExampleE(example_e::Msg),
     Matrix(matrix_form::Msg),
 }
 
 fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
     match msg {
+        Msg::Auth(msg) => {
+            auth::update(msg, &mut model.auth, &mut orders.proxy(Msg::Auth));
+        }
+        Msg::BatchConversion(msg) => {
+            batch_conversion::update(
+                msg,
+                &mut model.batch_conversion,
+                &mut orders.proxy(Msg::BatchConversion),
+            );
+        }
+        Msg::Chat(msg) => {
+            chat::update(msg, &mut model.chat, &mut orders.proxy(Msg::Chat));
+        }
         //Msg::ExampleA(msg) => {
         //    example_a::update(msg, &mut model.example_a, &mut orders.proxy(Msg::ExampleA));
         //}
@@ -58,9 +86,9 @@ fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
         //Msg::ExampleD(msg) => {
         //    example_d::update(msg, &mut model.example_d, &mut orders.proxy(Msg::ExampleD));
         //}
-        //Msg::ExampleE(msg) => {
-        //    example_e::update(msg, &mut model.example_e, &mut orders.proxy(Msg::ExampleE));
-        //}
+        Msg::ExampleE(msg) => {
+            example_e::update(msg, &mut model.example_e, &mut orders.proxy(Msg::ExampleE));
+        }
         Msg::Matrix(msg) => {
             matrix_form::update(msg, &mut model.matrix_form, &mut orders.proxy(Msg::Matrix));
         }
@@ -78,12 +106,15 @@ fn view(model: &Model) -> impl IntoNodes<Msg> {
             St::MaxWidth => px(800);
             St::Margin => "auto";
         },
+        auth::view(&model.auth, view_intro).map_msg(Msg::Auth),
         //example_a::view(&model.example_a, view_intro).map_msg(Msg::ExampleA),
         //example_b::view(&model.example_b, view_intro).map_msg(Msg::ExampleB),
         //example_c::view(&model.example_c, view_intro).map_msg(Msg::ExampleC),
         //example_d::view(&model.example_d, view_intro).map_msg(Msg::ExampleD),
-        //example_e::view(&model.example_e, view_intro).map_msg(Msg::ExampleE),
+        example_e::view(&model.example_e, view_intro).map_msg(Msg::ExampleE),
         matrix_form::view(&model.matrix_form, view_intro).map_msg(Msg::Matrix),
+        batch_conversion::view(&model.batch_conversion, view_intro).map_msg(Msg::BatchConversion),
+        chat::view(&model.chat, view_intro).map_msg(Msg::Chat),
     ]
 }
 
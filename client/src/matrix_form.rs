@@ -2,6 +2,8 @@ use seed::{prelude::*, *};
 use std::borrow::Cow;
 use std::mem;
 
+use crate::auth;
+
 pub const TITLE: &str = "Matrix";
 pub const DESCRIPTION: &str = "Rotation Matrix - fill form and be happy!";
 const MAT_LEN: usize = 9;
@@ -151,7 +153,7 @@ pub fn update(msg: Msg, model: &mut Model, orders: &mut impl Orders<Msg>) {
 }
 
 async fn send_rot_matrix(mat: shared::RotationMatrix) -> fetch::Result<shared::Quaternion> {
-    Request::new(get_request_url())
+    auth::request(get_request_url())
         .method(Method::Post)
         .json(&mat)?
         .fetch()
@@ -0,0 +1,121 @@
+use actix_web::dev::HttpResponseBuilder;
+use actix_web::http::Method;
+use actix_web::{web, HttpRequest, HttpResponse};
+use lazy_static::lazy_static;
+use rand::Rng;
+
+use crate::auth::AuthError;
+use crate::sessions::Sessions;
+use crate::user_actor::{validate_credentials, MsgPasswordHash, MsgRegister, UserError};
+use crate::State;
+
+fn argon2_config<'a>() -> argon2::Config<'a> {
+    argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        ..argon2::Config::default()
+    }
+}
+
+lazy_static! {
+    // Logins of unknown users are verified against it, so that they take as long
+    // as the logins of existing users and don't reveal which usernames exist.
+    static ref DUMMY_PASSWORD_HASH: String =
+        argon2::hash_encoded(b"dummy password", &[0; 16], &argon2_config())
+            .expect("hash dummy password");
+}
+
+// ---- Endpoints ----
+
+/// `POST /auth/register`, logs the new user in.
+pub async fn register(
+    state: web::Data<State>,
+    request_data: web::Json<shared::CredentialsRequestBody>,
+) -> Result<HttpResponse, UserError> {
+    let shared::CredentialsRequestBody { username, password } = request_data.into_inner();
+    validate_credentials(&username, &password)?;
+
+    // Hashing takes tens of milliseconds, it would block the worker.
+    let password_hash = web::block(move || {
+        let salt: [u8; 16] = rand::thread_rng().gen();
+        argon2::hash_encoded(password.as_bytes(), &salt, &argon2_config())
+    })
+    .await
+    .map_err(|error| UserError::Hashing(error.to_string()))?;
+
+    state
+        .user_actor
+        .send(MsgRegister {
+            username: username.clone(),
+            password_hash,
+        })
+        .await
+        .expect("send MsgRegister")?;
+    Ok(start_session(&state, username, HttpResponse::Created()))
+}
+
+/// `POST /auth/login`
+pub async fn login(
+    state: web::Data<State>,
+    request_data: web::Json<shared::CredentialsRequestBody>,
+) -> Result<HttpResponse, UserError> {
+    let shared::CredentialsRequestBody { username, password } = request_data.into_inner();
+    let password_hash = state
+        .user_actor
+        .send(MsgPasswordHash {
+            username: username.clone(),
+        })
+        .await
+        .expect("send MsgPasswordHash");
+
+    let known_user = password_hash.is_some();
+    let valid = web::block(move || {
+        let password_hash = password_hash.as_deref().unwrap_or(&DUMMY_PASSWORD_HASH);
+        argon2::verify_encoded(password_hash, password.as_bytes())
+    })
+    .await
+    .map_err(|error| UserError::Hashing(error.to_string()))?;
+    if !(valid && known_user) {
+        return Err(UserError::InvalidCredentials);
+    }
+    Ok(start_session(&state, username, HttpResponse::Ok()))
+}
+
+fn start_session(
+    state: &State,
+    username: String,
+    mut response: HttpResponseBuilder,
+) -> HttpResponse {
+    let (cookie, csrf_token) = state.auth.sessions().create(username.clone());
+    response.cookie(cookie).json(shared::SessionResponseBody {
+        username,
+        csrf_token,
+    })
+}
+
+/// `GET /auth/session`, the user of the session cookie.
+pub async fn session(
+    state: web::Data<State>,
+    req: HttpRequest,
+) -> Result<web::Json<shared::SessionResponseBody>, AuthError> {
+    let (username, csrf_token) = state
+        .auth
+        .sessions()
+        .find(&req, &Method::GET)?
+        .ok_or(AuthError::MissingCredentials)?;
+    Ok(web::Json(shared::SessionResponseBody {
+        username,
+        csrf_token,
+    }))
+}
+
+/// `POST /auth/logout`, needs the CSRF token.
+pub async fn logout(state: web::Data<State>, req: HttpRequest) -> Result<HttpResponse, AuthError> {
+    let sessions = state.auth.sessions();
+    sessions
+        .find(&req, &Method::POST)?
+        .ok_or(AuthError::MissingCredentials)?;
+    sessions.remove(&req);
+    Ok(HttpResponse::NoContent()
+        .del_cookie(&Sessions::removal_cookie())
+        .finish())
+}
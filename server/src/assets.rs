@@ -0,0 +1,377 @@
+use actix_files::file_extension_to_mime;
+#[cfg(not(feature = "embed-client"))]
+use actix_files::NamedFile;
+use actix_web::http::header::{self, ContentEncoding, HeaderMap, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{error, web, Error, HttpRequest, HttpResponse, Result};
+#[cfg(not(feature = "embed-client"))]
+use std::io;
+#[cfg(not(feature = "embed-client"))]
+use std::path::{Path, PathBuf};
+#[cfg(not(feature = "embed-client"))]
+use tracing::warn;
+
+use crate::State;
+
+// Hashed file names change with their content.
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+// Cached, but revalidated with the ETag on every use.
+const NO_CACHE: &str = "no-cache";
+
+// Never client-side routes, whatever `client_routes` says.
+const SERVER_PREFIXES: [&str; 4] = ["/api/", "/pkg/", "/auth/", "/admin/"];
+
+// Shortest hex segment treated as a content hash.
+const MIN_HASH_LEN: usize = 8;
+
+// Preferred first.
+const PRECOMPRESSED: [(ContentEncoding, &str); 2] =
+    [(ContentEncoding::Br, "br"), (ContentEncoding::Gzip, "gz")];
+
+// Served instead of a missing `index_file`.
+#[cfg(not(feature = "embed-client"))]
+const FALLBACK_PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8">
+  <title>Client not available</title>
+</head>
+<body>
+  <h1>Client not available</h1>
+  <p>The server is running, but the client wasn't found.
+  Build it with <code>cargo make build</code> or check <code>index_file</code> and <code>pkg_folder</code>.</p>
+</body>
+</html>
+"#;
+
+// ---- Endpoints ----
+
+/// Serves the client bundle from `pkg_folder`,
+/// with a precompressed `.br` or `.gz` sibling if the client accepts it.
+/// Other files are compressed on the fly by the `Compress` middleware.
+#[cfg(not(feature = "embed-client"))]
+pub async fn pkg(req: HttpRequest, state: web::Data<State>) -> Result<HttpResponse> {
+    let path = asset_path(&state.config.pkg_folder, req.match_info().query("path"))
+        .filter(|path| path.is_file())
+        .ok_or_else(not_found)?;
+    let name = path.to_string_lossy().into_owned();
+
+    let precompressed = precompressed(&req, |extension| {
+        let compressed = PathBuf::from(format!("{}.{}", name, extension));
+        Some(compressed).filter(|compressed| compressed.is_file())
+    });
+    let mut response = match &precompressed {
+        Some((compressed, _)) => NamedFile::open(compressed)?,
+        None => NamedFile::open(&path)?,
+    }
+    .set_content_type(content_type(&name))
+    .disable_content_disposition()
+    .into_response(&req)?;
+
+    let encoding = precompressed.map(|(_, encoding)| encoding);
+    asset_headers(response.headers_mut(), &name, encoding);
+    Ok(response)
+}
+
+/// Serves the client bundle embedded from `client/pkg`,
+/// with a precompressed `.br` or `.gz` sibling if the client accepts it.
+/// Other files are compressed on the fly by the `Compress` middleware.
+#[cfg(feature = "embed-client")]
+pub async fn pkg(req: HttpRequest) -> Result<HttpResponse> {
+    let name = req.match_info().query("path").trim_start_matches('/');
+
+    let precompressed = precompressed(&req, |extension| {
+        let compressed = format!("{}.{}", name, extension);
+        embedded::Pkg::get(&compressed).map(|content| (compressed, content))
+    });
+    let (served_name, content, encoding) = match precompressed {
+        Some(((compressed, content), encoding)) => (compressed, content, Some(encoding)),
+        None => {
+            let content = embedded::Pkg::get(name).ok_or_else(not_found)?;
+            (name.to_owned(), content, None)
+        }
+    };
+    let mut response = embedded::response(&req, &served_name, content, content_type(name));
+
+    asset_headers(response.headers_mut(), name, encoding);
+    Ok(response)
+}
+
+/// Serves the index for client-side routes matching `client_routes`
+/// and `404 Not Found` for everything else, e.g. mistyped asset paths.
+/// The index is never cached without revalidation, so that clients pick up new bundles.
+pub async fn client_route(req: HttpRequest, state: web::Data<State>) -> Result<HttpResponse> {
+    if !is_client_route(&req, &state.config.client_routes) {
+        return Err(not_found());
+    }
+    let mut response = index(&req, &state)?;
+    response
+        .headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static(NO_CACHE));
+    Ok(response)
+}
+
+// `index_file` or the built-in fallback page if it doesn't exist.
+#[cfg(not(feature = "embed-client"))]
+fn index(req: &HttpRequest, state: &State) -> Result<HttpResponse> {
+    match NamedFile::open(&state.config.index_file) {
+        Ok(file) => file.into_response(req),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            warn!(
+                "index_file '{}' not found, serving the fallback page",
+                state.config.index_file.display()
+            );
+            Ok(HttpResponse::ServiceUnavailable()
+                .content_type("text/html; charset=utf-8")
+                .body(FALLBACK_PAGE))
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+#[cfg(feature = "embed-client")]
+fn index(req: &HttpRequest, _: &State) -> Result<HttpResponse> {
+    Ok(embedded::response(
+        req,
+        embedded::INDEX_NAME,
+        embedded::INDEX.into(),
+        content_type(embedded::INDEX_NAME),
+    ))
+}
+
+// ---- Helpers ----
+
+fn not_found() -> Error {
+    error::ErrorNotFound("Not found")
+}
+
+// `None` for paths that could leave `folder` or reach hidden files.
+#[cfg(not(feature = "embed-client"))]
+fn asset_path(folder: &Path, tail: &str) -> Option<PathBuf> {
+    let mut path = folder.to_owned();
+    for segment in tail.split('/').filter(|segment| !segment.is_empty()) {
+        if segment.starts_with('.') || segment.contains('\\') {
+            return None;
+        }
+        path.push(segment);
+    }
+    Some(path)
+}
+
+// `GET` or `HEAD` of a page, not a file, on a path matching one of `patterns`.
+fn is_client_route(req: &HttpRequest, patterns: &[String]) -> bool {
+    let path = req.path();
+    let is_server_path = SERVER_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix) || path == prefix.trim_end_matches('/'));
+    if is_server_path {
+        return false;
+    }
+    let is_page = (req.method() == Method::GET || req.method() == Method::HEAD)
+        && !path.rsplit('/').next().unwrap_or_default().contains('.')
+        && accepts_html(req);
+    is_page
+        && patterns
+            .iter()
+            .any(|pattern| matches_pattern(pattern, path))
+}
+
+// Browsers navigating to a page accept `text/html`, scripts fetching data usually don't.
+fn accepts_html(req: &HttpRequest) -> bool {
+    match req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(accept) => accept
+            .split(',')
+            .map(|item| item.split(';').next().unwrap_or_default().trim())
+            .any(|media_type| matches!(media_type, "text/html" | "text/*" | "*/*")),
+        None => true,
+    }
+}
+
+// `*` matches one path segment, `**` any number of segments.
+fn matches_pattern(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<_> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let path: Vec<_> = path.split('/').filter(|s| !s.is_empty()).collect();
+    matches_segments(&pattern, &path)
+}
+
+fn matches_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, None) => true,
+        (Some((&"**", rest)), _) => {
+            matches_segments(rest, path)
+                || (!path.is_empty() && matches_segments(pattern, &path[1..]))
+        }
+        (Some((&segment, rest)), Some((&path_segment, path_rest))) => {
+            (segment == "*" || segment == path_segment) && matches_segments(rest, path_rest)
+        }
+        _ => false,
+    }
+}
+
+fn content_type(name: &str) -> mime::Mime {
+    file_extension_to_mime(name.rsplit('.').next().unwrap_or_default())
+}
+
+// The first variant found by `find` with the file extension of an encoding the client accepts.
+fn precompressed<T>(
+    req: &HttpRequest,
+    find: impl Fn(&str) -> Option<T>,
+) -> Option<(T, ContentEncoding)> {
+    PRECOMPRESSED
+        .iter()
+        .filter(|(encoding, _)| accepts(req, encoding.as_str()))
+        .find_map(|(encoding, extension)| find(extension).map(|variant| (variant, *encoding)))
+}
+
+fn accepts(req: &HttpRequest, encoding: &str) -> bool {
+    req.headers()
+        .get_all(header::ACCEPT_ENCODING)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default();
+            let rejected = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|quality| quality.parse::<f32>().ok())
+                    .is_some_and(|quality| quality <= 0.)
+            });
+            name.eq_ignore_ascii_case(encoding) && !rejected
+        })
+}
+
+fn asset_headers(headers: &mut HeaderMap, name: &str, encoding: Option<ContentEncoding>) {
+    if let Some(encoding) = encoding {
+        // The header keeps `Compress` from encoding the body again.
+        headers.insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+    }
+    headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    let cache_control = if is_hashed(name) { IMMUTABLE } else { NO_CACHE };
+    headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(cache_control),
+    );
+}
+
+// E.g. `client-5f3c2a1b.wasm` or `app.5f3c2a1b.js`.
+fn is_hashed(name: &str) -> bool {
+    std::path::Path::new(name)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .is_some_and(|stem| {
+            stem.split(['-', '.', '_']).any(|part| {
+                part.len() >= MIN_HASH_LEN && part.chars().all(|c| c.is_ascii_hexdigit())
+            })
+        })
+}
+
+// ---- Embedded client ----
+
+#[cfg(feature = "embed-client")]
+mod embedded {
+    use actix_web::http::header::{self, EntityTag, IfNoneMatch};
+    use actix_web::{web, HttpMessage, HttpRequest, HttpResponse};
+    use lazy_static::lazy_static;
+    use rust_embed::RustEmbed;
+    use std::borrow::Cow;
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::HashMap;
+    use std::hash::{Hash, Hasher};
+
+    pub const INDEX_NAME: &str = "index.html";
+    pub const INDEX: &[u8] = include_bytes!("../../client/index.html");
+
+    // The client has to be built before the server, the folder is read at compile time.
+    #[derive(RustEmbed)]
+    #[folder = "../client/pkg/"]
+    pub struct Pkg;
+
+    lazy_static! {
+        // Content hashes, computed once as the embedded files never change.
+        static ref ETAGS: HashMap<String, EntityTag> = {
+            let mut etags: HashMap<_, _> = Pkg::iter()
+                .filter_map(|name| {
+                    let content = Pkg::get(&name)?;
+                    Some((name.into_owned(), etag(&content)))
+                })
+                .collect();
+            etags.insert(INDEX_NAME.to_owned(), etag(INDEX));
+            etags
+        };
+    }
+
+    fn etag(content: &[u8]) -> EntityTag {
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        EntityTag::strong(format!("{:x}-{:x}", content.len(), hasher.finish()))
+    }
+
+    /// `200` with the content, or `304 Not Modified` for a matching `If-None-Match`.
+    pub fn response(
+        req: &HttpRequest,
+        name: &str,
+        content: Cow<'static, [u8]>,
+        content_type: mime::Mime,
+    ) -> HttpResponse {
+        let etag = ETAGS.get(name).cloned().unwrap_or_else(|| etag(&content));
+        let not_modified = match req.get_header::<IfNoneMatch>() {
+            Some(IfNoneMatch::Any) => true,
+            Some(IfNoneMatch::Items(items)) => items.iter().any(|item| item.weak_eq(&etag)),
+            None => false,
+        };
+        if not_modified {
+            return HttpResponse::NotModified()
+                .header(header::ETAG, etag.to_string())
+                .finish();
+        }
+        let body = match content {
+            Cow::Borrowed(content) => web::Bytes::from_static(content),
+            Cow::Owned(content) => web::Bytes::from(content),
+        };
+        HttpResponse::Ok()
+            .content_type(content_type.to_string())
+            .header(header::ETAG, etag.to_string())
+            .body(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn page_request(path: &str) -> HttpRequest {
+        TestRequest::get()
+            .uri(path)
+            .header(header::ACCEPT, "text/html")
+            .to_http_request()
+    }
+
+    #[test]
+    fn client_routes() {
+        let all = vec!["/**".to_owned()];
+        assert!(is_client_route(&page_request("/"), &all));
+        assert!(is_client_route(&page_request("/chat/general"), &all));
+        assert!(!is_client_route(&page_request("/styles.css"), &all));
+
+        let only_chat = vec!["/chat/*".to_owned()];
+        assert!(is_client_route(&page_request("/chat/general"), &only_chat));
+        assert!(!is_client_route(&page_request("/chat/a/b"), &only_chat));
+    }
+
+    #[test]
+    fn server_paths_are_never_client_routes() {
+        let all = vec!["/**".to_owned()];
+        for path in &["/api/x", "/auth/x", "/admin/x", "/admin", "/pkg/x"] {
+            assert!(!is_client_route(&page_request(path), &all), "{}", path);
+        }
+    }
+}
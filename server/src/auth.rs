@@ -0,0 +1,425 @@
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, Method, StatusCode};
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use futures::future::{self, Either, Ready};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::AuthConfig;
+use crate::sessions::Sessions;
+use crate::State;
+
+const API_KEY_HEADER: &str = "x-api-key";
+
+const BEARER_PREFIX: &str = "Bearer ";
+
+// Browsers can't set headers on WebSocket and EventSource requests.
+const ACCESS_TOKEN_PARAM: &str = "access_token";
+
+// Subjects are namespaced by the authenticator,
+// so an API key named like a user can't act as that user.
+const API_KEY_SUBJECT_PREFIX: &str = "api_key:";
+const USER_SUBJECT_PREFIX: &str = "user:";
+
+/// Who sent the request and what it may do,
+/// available in the request extensions of authenticated routes.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// `api_key:<name>` for API keys and their tokens, `user:<username>` for sessions.
+    pub subject: String,
+    pub scopes: Vec<String>,
+}
+
+impl Identity {
+    pub fn api_key(name: &str, scopes: Vec<String>) -> Self {
+        Self {
+            subject: format!("{}{}", API_KEY_SUBJECT_PREFIX, name),
+            scopes,
+        }
+    }
+
+    pub fn user(username: &str, scopes: Vec<String>) -> Self {
+        Self {
+            subject: format!("{}{}", USER_SUBJECT_PREFIX, username),
+            scopes,
+        }
+    }
+
+    /// `None` unless the request comes from a logged-in user.
+    pub fn username(&self) -> Option<&str> {
+        self.subject.strip_prefix(USER_SUBJECT_PREFIX)
+    }
+
+    /// `*` grants everything and `<resource>:*` both accesses to the resource.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        let resource = scope.split(':').next().unwrap_or_default();
+        self.scopes.iter().any(|granted| {
+            granted == "*" || granted == scope || granted.strip_suffix(":*") == Some(resource)
+        })
+    }
+}
+
+impl FromRequest for Identity {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        future::ready(
+            req.extensions()
+                .get::<Identity>()
+                .cloned()
+                .ok_or(AuthError::MissingCredentials),
+        )
+    }
+}
+
+/// `<resource>:read` for `GET` requests and `<resource>:write` otherwise,
+/// where the resource is the first path segment after `/api/`,
+/// e.g. `counters:write` for `POST /api/counters/{name}/increment`.
+pub fn required_scope(method: &Method, path: &str) -> String {
+    let resource = path
+        .trim_start_matches('/')
+        .trim_start_matches("api/")
+        .split('/')
+        .next()
+        .unwrap_or_default();
+    match resource {
+        // Chat messages are sent over the socket opened by a `GET`.
+        "send-message" | "chat" => "messages:write".to_owned(),
+        resource if method == Method::GET || method == Method::HEAD => {
+            format!("{}:read", resource)
+        }
+        resource => format!("{}:write", resource),
+    }
+}
+
+// ---- Authenticators ----
+
+/// A way to identify the sender of a request.
+pub trait Authenticator {
+    /// `Ok(None)` when the request doesn't carry this kind of credentials.
+    fn authenticate(&self, req: &ServiceRequest) -> Result<Option<Identity>, AuthError>;
+}
+
+/// Static keys from `auth.api_keys` sent in the `x-api-key` header.
+pub struct ApiKeys(HashMap<String, Identity>);
+
+impl ApiKeys {
+    fn identity(&self, api_key: &str) -> Result<Identity, AuthError> {
+        self.0.get(api_key).cloned().ok_or(AuthError::InvalidApiKey)
+    }
+}
+
+impl Authenticator for ApiKeys {
+    fn authenticate(&self, req: &ServiceRequest) -> Result<Option<Identity>, AuthError> {
+        match req.headers().get(API_KEY_HEADER) {
+            Some(value) => {
+                let api_key = value.to_str().map_err(|_| AuthError::InvalidApiKey)?;
+                self.identity(api_key).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    scopes: Vec<String>,
+    iat: u64,
+    exp: u64,
+}
+
+/// HS256 JWTs signed with `auth.jwt_secret`, sent as `Authorization: Bearer <token>`
+/// or in the `access_token` query parameter.
+pub struct Tokens {
+    secret: Vec<u8>,
+    ttl_secs: u64,
+}
+
+impl Tokens {
+    fn issue(&self, identity: &Identity) -> Result<String, AuthError> {
+        let now = unix_time();
+        let claims = Claims {
+            sub: identity.subject.clone(),
+            scopes: identity.scopes.clone(),
+            iat: now,
+            exp: now + self.ttl_secs,
+        };
+        jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(&self.secret),
+        )
+        .map_err(|error| AuthError::Signing(error.to_string()))
+    }
+
+    fn verify(&self, token: &str) -> Result<Identity, AuthError> {
+        let claims = jsonwebtoken::decode::<Claims>(
+            token,
+            &DecodingKey::from_secret(&self.secret),
+            &Validation::new(Algorithm::HS256),
+        )
+        .map_err(|error| AuthError::InvalidToken(error.to_string()))?
+        .claims;
+        Ok(Identity {
+            subject: claims.sub,
+            scopes: claims.scopes,
+        })
+    }
+}
+
+impl Authenticator for Tokens {
+    fn authenticate(&self, req: &ServiceRequest) -> Result<Option<Identity>, AuthError> {
+        let bearer = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix(BEARER_PREFIX));
+        let query_token = || {
+            web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                .ok()
+                .and_then(|mut query| query.remove(ACCESS_TOKEN_PARAM))
+        };
+        match bearer.map(ToOwned::to_owned).or_else(query_token) {
+            Some(token) => self.verify(&token).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time after unix epoch")
+        .as_secs()
+}
+
+/// All configured authenticators, tried in order.
+pub struct Auth {
+    api_keys: ApiKeys,
+    // `None` without `auth.jwt_secret`.
+    tokens: Option<Tokens>,
+    sessions: Sessions,
+}
+
+impl Auth {
+    pub fn new(config: &AuthConfig, sessions: Sessions) -> Self {
+        let api_keys = config
+            .api_keys
+            .iter()
+            .map(|api_key| {
+                let identity = Identity::api_key(&api_key.name, api_key.scopes.clone());
+                (api_key.key.clone(), identity)
+            })
+            .collect();
+        Self {
+            api_keys: ApiKeys(api_keys),
+            tokens: config.jwt_secret.as_ref().map(|secret| Tokens {
+                secret: secret.as_bytes().to_vec(),
+                ttl_secs: config.token_ttl_secs,
+            }),
+            sessions,
+        }
+    }
+
+    pub fn sessions(&self) -> &Sessions {
+        &self.sessions
+    }
+
+    fn authenticators(&self) -> Vec<&dyn Authenticator> {
+        let mut authenticators: Vec<&dyn Authenticator> = vec![&self.api_keys];
+        if let Some(tokens) = &self.tokens {
+            authenticators.push(tokens);
+        }
+        authenticators.push(&self.sessions);
+        authenticators
+    }
+
+    pub fn authenticate(&self, req: &ServiceRequest) -> Result<Identity, AuthError> {
+        for authenticator in self.authenticators() {
+            if let Some(identity) = authenticator.authenticate(req)? {
+                return Ok(identity);
+            }
+        }
+        Err(AuthError::MissingCredentials)
+    }
+
+    /// Exchanges an API key for a bearer token with the same scopes.
+    pub fn issue_token(&self, api_key: &str) -> Result<shared::TokenResponseBody, AuthError> {
+        let tokens = self.tokens.as_ref().ok_or(AuthError::TokensDisabled)?;
+        let identity = self.api_keys.identity(api_key)?;
+        Ok(shared::TokenResponseBody {
+            token: tokens.issue(&identity)?,
+            expires_in: tokens.ttl_secs,
+        })
+    }
+}
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingCredentials,
+    InvalidApiKey,
+    InvalidToken(String),
+    InvalidSession,
+    InvalidCsrfToken,
+    MissingScope(String),
+    TokensDisabled,
+    Signing(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingCredentials => {
+                write!(f, "Missing API key, bearer token or session cookie")
+            }
+            Self::InvalidApiKey => write!(f, "Invalid API key"),
+            Self::InvalidToken(reason) => write!(f, "Invalid bearer token: {}", reason),
+            Self::InvalidSession => write!(f, "Session expired, log in again"),
+            Self::InvalidCsrfToken => write!(f, "Missing or invalid CSRF token"),
+            Self::MissingScope(scope) => write!(f, "Missing scope '{}'", scope),
+            Self::TokensDisabled => write!(f, "Bearer tokens aren't enabled"),
+            Self::Signing(reason) => write!(f, "Can't sign token: {}", reason),
+        }
+    }
+}
+
+impl ResponseError for AuthError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingCredentials
+            | Self::InvalidApiKey
+            | Self::InvalidToken(_)
+            | Self::InvalidSession => StatusCode::UNAUTHORIZED,
+            Self::InvalidCsrfToken | Self::MissingScope(_) => StatusCode::FORBIDDEN,
+            Self::TokensDisabled => StatusCode::NOT_FOUND,
+            Self::Signing(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if self.status_code() == StatusCode::UNAUTHORIZED {
+            response.header(header::WWW_AUTHENTICATE, "Bearer");
+        }
+        response.body(self.to_string())
+    }
+}
+
+// ---- Endpoint ----
+
+/// `POST /auth/token`
+pub async fn token(
+    state: web::Data<State>,
+    request_data: web::Json<shared::TokenRequestBody>,
+) -> Result<web::Json<shared::TokenResponseBody>, AuthError> {
+    state.auth.issue_token(&request_data.api_key).map(web::Json)
+}
+
+// ---- Middleware ----
+
+/// Puts the `Identity` of authenticated requests into the request extensions.
+/// With `enforce` it rejects requests without valid credentials
+/// or the scope required by `required_scope`.
+pub struct Authenticate {
+    auth: Arc<Auth>,
+    enforce: bool,
+}
+
+impl Authenticate {
+    pub fn new(auth: Arc<Auth>, enforce: bool) -> Self {
+        Self { auth, enforce }
+    }
+}
+
+impl<S, B> Transform<S> for Authenticate
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthenticateMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(AuthenticateMiddleware {
+            service,
+            auth: self.auth.clone(),
+            enforce: self.enforce,
+        })
+    }
+}
+
+pub struct AuthenticateMiddleware<S> {
+    service: S,
+    auth: Arc<Auth>,
+    enforce: bool,
+}
+
+impl<S, B> Service for AuthenticateMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if !self.enforce {
+            if let Ok(identity) = self.auth.authenticate(&req) {
+                req.extensions_mut().insert(identity);
+            }
+            return Either::Left(self.service.call(req));
+        }
+        let scope = required_scope(req.method(), req.path());
+        let identity = self.auth.authenticate(&req).and_then(|identity| {
+            if identity.has_scope(&scope) {
+                Ok(identity)
+            } else {
+                Err(AuthError::MissingScope(scope))
+            }
+        });
+        match identity {
+            Ok(identity) => {
+                req.extensions_mut().insert(identity);
+                Either::Left(self.service.call(req))
+            }
+            Err(error) => Either::Right(future::ok(req.error_response(error))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_sessions_have_a_username() {
+        let user = Identity::user("alice", Vec::new());
+        assert_eq!(user.username(), Some("alice"));
+
+        let api_key = Identity::api_key("alice", Vec::new());
+        assert_eq!(api_key.username(), None);
+        assert_ne!(api_key.subject, user.subject);
+    }
+}
@@ -0,0 +1,479 @@
+use actix::prelude::*;
+use actix_http::body::BodyStream;
+use actix_web::dev::{
+    Body, MessageBody, ResponseBody, Service, ServiceRequest, ServiceResponse, Transform,
+};
+use actix_web::http::StatusCode;
+use actix_web::{error, web, Error, HttpResponse, ResponseError};
+use futures::future::{self, LocalBoxFuture, Ready};
+use futures::stream::{self, StreamExt};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+use std::task::{self, Poll};
+use std::time::Duration;
+
+use crate::config::{ChaosConfig, ChaosRule};
+use crate::{metrics, State};
+
+const ERROR_STATUSES: [StatusCode; 4] = [
+    StatusCode::INTERNAL_SERVER_ERROR,
+    StatusCode::BAD_GATEWAY,
+    StatusCode::SERVICE_UNAVAILABLE,
+    StatusCode::GATEWAY_TIMEOUT,
+];
+
+// Never get faults, so chaos can always be turned off and probes stay reliable.
+const EXEMPT_PREFIXES: [&str; 3] = ["/admin/", "/healthz", "/readyz"];
+
+// ---- Actor ----
+
+/// Decides which faults to inject into each request.
+/// All random draws come from one generator, so a fixed seed
+/// and the same request order give the same faults.
+pub struct ChaosActor {
+    config: ChaosConfig,
+    rng: StdRng,
+}
+
+impl ChaosActor {
+    pub fn new(config: ChaosConfig) -> Self {
+        let rng = new_rng(config.seed);
+        Self { config, rng }
+    }
+
+    // The rule of the longest configured prefix of `path`.
+    fn rule(&self, path: &str) -> Option<&ChaosRule> {
+        self.config
+            .routes
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, rule)| rule)
+    }
+
+    fn decide(&mut self, path: &str) -> Faults {
+        if !self.config.enabled {
+            return Faults::default();
+        }
+        let rule = match self.rule(path) {
+            Some(rule) => *rule,
+            None => return Faults::default(),
+        };
+        let rng = &mut self.rng;
+
+        let latency = if rng.gen_bool(rule.latency_probability) {
+            Some(Duration::from_millis(
+                rng.gen_range(0, rule.latency_ms.saturating_add(1)),
+            ))
+        } else {
+            None
+        };
+        // One draw picks at most one of the failures.
+        let roll: f64 = rng.gen();
+        let outcome = if roll < rule.error_probability {
+            let status = *ERROR_STATUSES.choose(rng).expect("non-empty statuses");
+            Some(Outcome::Error(status))
+        } else if roll < rule.error_probability + rule.truncate_probability {
+            Some(Outcome::Truncate)
+        } else if roll < rule.error_probability + rule.truncate_probability + rule.reset_probability
+        {
+            Some(Outcome::Reset)
+        } else {
+            None
+        };
+        Faults { latency, outcome }
+    }
+
+    fn settings(&self) -> ChaosSettings {
+        ChaosSettings {
+            enabled: self.config.enabled,
+            seed: self.config.seed,
+            routes: self.config.routes.clone(),
+        }
+    }
+}
+
+impl Actor for ChaosActor {
+    type Context = Context<Self>;
+}
+
+fn new_rng(seed: Option<u64>) -> StdRng {
+    seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Error(StatusCode),
+    Truncate,
+    Reset,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Faults {
+    latency: Option<Duration>,
+    outcome: Option<Outcome>,
+}
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub struct ChaosError(StatusCode);
+
+impl fmt::Display for ChaosError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Injected {} error", self.0.as_u16())
+    }
+}
+
+impl ResponseError for ChaosError {
+    fn status_code(&self) -> StatusCode {
+        self.0
+    }
+}
+
+// ---- Messages ----
+
+pub struct MsgDecide {
+    pub path: String,
+}
+
+impl Message for MsgDecide {
+    type Result = Faults;
+}
+
+/// Changes the given settings, `seed` restarts the random sequence.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MsgUpdate {
+    pub enabled: Option<bool>,
+    pub seed: Option<u64>,
+    pub routes: Option<BTreeMap<String, ChaosRule>>,
+}
+
+impl Message for MsgUpdate {
+    type Result = ChaosSettings;
+}
+
+pub struct MsgSettings;
+
+impl Message for MsgSettings {
+    type Result = ChaosSettings;
+}
+
+#[derive(Serialize)]
+pub struct ChaosSettings {
+    enabled: bool,
+    seed: Option<u64>,
+    routes: BTreeMap<String, ChaosRule>,
+}
+
+// ---- Handlers ----
+
+impl Handler<MsgDecide> for ChaosActor {
+    type Result = MessageResult<MsgDecide>;
+
+    fn handle(&mut self, msg: MsgDecide, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.decide(&msg.path))
+    }
+}
+
+impl Handler<MsgUpdate> for ChaosActor {
+    type Result = MessageResult<MsgUpdate>;
+
+    fn handle(&mut self, msg: MsgUpdate, _: &mut Context<Self>) -> Self::Result {
+        if let Some(enabled) = msg.enabled {
+            self.config.enabled = enabled;
+        }
+        if let Some(seed) = msg.seed {
+            self.config.seed = Some(seed);
+            self.rng = new_rng(Some(seed));
+        }
+        if let Some(routes) = msg.routes {
+            self.config.routes = routes;
+        }
+        MessageResult(self.settings())
+    }
+}
+
+impl Handler<MsgSettings> for ChaosActor {
+    type Result = MessageResult<MsgSettings>;
+
+    fn handle(&mut self, _: MsgSettings, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(self.settings())
+    }
+}
+
+// ---- Admin endpoints ----
+
+pub async fn settings(state: web::Data<State>) -> HttpResponse {
+    let settings = state
+        .chaos_actor
+        .send(MsgSettings)
+        .await
+        .expect("send MsgSettings");
+    HttpResponse::Ok().json(settings)
+}
+
+pub async fn update(
+    state: web::Data<State>,
+    request_data: web::Json<MsgUpdate>,
+) -> Result<HttpResponse, Error> {
+    for (prefix, rule) in request_data.routes.iter().flatten() {
+        rule.validate()
+            .map_err(|reason| error::ErrorBadRequest(format!("'{}' {}", prefix, reason)))?;
+    }
+    let settings = state
+        .chaos_actor
+        .send(request_data.into_inner())
+        .await
+        .expect("send MsgUpdate");
+    Ok(HttpResponse::Ok().json(settings))
+}
+
+// ---- Middleware ----
+
+/// Injects latency, `5xx` errors, truncated bodies and connection resets
+/// into requests matching `chaos.routes`, as decided by `ChaosActor`.
+/// The admin and health endpoints are exempt.
+pub struct Chaos {
+    actor: Addr<ChaosActor>,
+}
+
+impl Chaos {
+    pub fn new(actor: Addr<ChaosActor>) -> Self {
+        Self { actor }
+    }
+}
+
+impl<S, B> Transform<S> for Chaos
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ChaosMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(ChaosMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            actor: self.actor.clone(),
+        })
+    }
+}
+
+pub struct ChaosMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    actor: Addr<ChaosActor>,
+}
+
+impl<S, B> Service for ChaosMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + Unpin + 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        if is_exempt(req.path()) {
+            let response = self.service.borrow_mut().call(req);
+            return Box::pin(async move {
+                Ok(response
+                    .await?
+                    .map_body(|_, body| ResponseBody::Body(Body::from_message(body))))
+            });
+        }
+        let decide = self.actor.send(MsgDecide {
+            path: req.path().to_owned(),
+        });
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let faults = decide.await.map_err(error::ErrorInternalServerError)?;
+            if let Some(latency) = faults.latency {
+                metrics::inc_chaos_faults("latency");
+                futures_timer::Delay::new(latency).await;
+            }
+            match faults.outcome {
+                Some(Outcome::Error(status)) => {
+                    metrics::inc_chaos_faults("error");
+                    Ok(req.error_response(ChaosError(status)))
+                }
+                // The handler isn't called, as if the connection failed before.
+                Some(Outcome::Reset) => {
+                    metrics::inc_chaos_faults("reset");
+                    let reset = stream::iter(vec![Err::<web::Bytes, _>(connection_closed())]);
+                    Ok(req.into_response(HttpResponse::Ok().streaming(reset)))
+                }
+                Some(Outcome::Truncate) => {
+                    metrics::inc_chaos_faults("truncate");
+                    let response = service.borrow_mut().call(req);
+                    Ok(response.await?.map_body(|_, body| {
+                        // Half of the first chunk, then the connection is closed.
+                        let truncated = Box::pin(body)
+                            .take(1)
+                            .map(|chunk| chunk.map(|bytes| bytes.slice(..bytes.len() / 2)))
+                            .chain(stream::once(future::err(connection_closed())));
+                        ResponseBody::Body(Body::from_message(BodyStream::new(truncated)))
+                    }))
+                }
+                None => {
+                    let response = service.borrow_mut().call(req);
+                    Ok(response
+                        .await?
+                        .map_body(|_, body| ResponseBody::Body(Body::from_message(body))))
+                }
+            }
+        })
+    }
+}
+
+fn is_exempt(path: &str) -> bool {
+    EXEMPT_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+}
+
+// A body stream error makes actix-web close the connection.
+fn connection_closed() -> Error {
+    error::ErrorInternalServerError("connection closed by chaos middleware")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    fn config(rule: ChaosRule) -> ChaosConfig {
+        ChaosConfig {
+            enabled: true,
+            seed: Some(42),
+            routes: vec![("/".to_owned(), rule)].into_iter().collect(),
+            ..ChaosConfig::default()
+        }
+    }
+
+    const RULE: ChaosRule = ChaosRule {
+        latency_ms: 100,
+        latency_probability: 0.5,
+        error_probability: 0.25,
+        truncate_probability: 0.25,
+        reset_probability: 0.25,
+    };
+
+    fn faults(latency_ms: Option<u64>, outcome: Option<Outcome>) -> Faults {
+        Faults {
+            latency: latency_ms.map(Duration::from_millis),
+            outcome,
+        }
+    }
+
+    fn expected_faults() -> Vec<Faults> {
+        vec![
+            faults(None, Some(Outcome::Truncate)),
+            faults(Some(16), Some(Outcome::Truncate)),
+            faults(None, Some(Outcome::Truncate)),
+            faults(
+                Some(37),
+                Some(Outcome::Error(StatusCode::SERVICE_UNAVAILABLE)),
+            ),
+            faults(None, Some(Outcome::Error(StatusCode::BAD_GATEWAY))),
+            faults(
+                None,
+                Some(Outcome::Error(StatusCode::INTERNAL_SERVER_ERROR)),
+            ),
+            faults(Some(75), Some(Outcome::Reset)),
+            faults(None, Some(Outcome::Truncate)),
+            faults(Some(29), None),
+            faults(Some(66), Some(Outcome::Reset)),
+        ]
+    }
+
+    #[test]
+    fn seed_gives_a_fixed_sequence_of_faults() {
+        let mut actor = ChaosActor::new(config(RULE));
+        let faults = (0..10)
+            .map(|_| actor.decide("/api/counter"))
+            .collect::<Vec<_>>();
+        assert_eq!(faults, expected_faults());
+    }
+
+    #[test]
+    fn no_faults_when_disabled_or_without_rule() {
+        let mut actor = ChaosActor::new(ChaosConfig {
+            routes: vec![("/api/".to_owned(), RULE)].into_iter().collect(),
+            ..config(RULE)
+        });
+        assert_eq!(actor.decide("/index.html"), Faults::default());
+
+        actor.config.enabled = false;
+        assert_eq!(actor.decide("/api/counter"), Faults::default());
+    }
+
+    #[actix_rt::test]
+    async fn update_with_seed_restarts_the_sequence() {
+        let actor = ChaosActor::new(config(RULE)).start();
+        let decide = || MsgDecide {
+            path: "/api/counter".to_owned(),
+        };
+        for _ in 0..3 {
+            actor.send(decide()).await.unwrap();
+        }
+        actor
+            .send(MsgUpdate {
+                enabled: None,
+                seed: Some(42),
+                routes: None,
+            })
+            .await
+            .unwrap();
+
+        let mut faults = Vec::new();
+        for _ in 0..3 {
+            faults.push(actor.send(decide()).await.unwrap());
+        }
+        assert_eq!(faults, &expected_faults()[..3]);
+    }
+
+    #[actix_rt::test]
+    async fn admin_and_health_routes_are_exempt() {
+        let rule = ChaosRule {
+            error_probability: 1.,
+            ..ChaosRule::default()
+        };
+        let actor = ChaosActor::new(config(rule)).start();
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Chaos::new(actor))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for path in &["/healthz", "/readyz", "/admin/chaos"] {
+            let request = test::TestRequest::get().uri(path).to_request();
+            let response = test::call_service(&mut app, request).await;
+            assert_eq!(response.status(), StatusCode::OK, "{}", path);
+        }
+        let request = test::TestRequest::get().uri("/api/counter").to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert!(response.status().is_server_error());
+    }
+}
@@ -0,0 +1,78 @@
+use actix::prelude::*;
+use std::collections::HashMap;
+
+// ---- Actor ----
+
+/// Broadcasts every sent message to all connected chat sessions.
+#[derive(Default)]
+pub struct ChatActor {
+    sessions: HashMap<usize, Recipient<MsgChatMessage>>,
+    next_session_id: usize,
+}
+
+impl Actor for ChatActor {
+    type Context = Context<Self>;
+}
+
+// ---- Messages ----
+
+/// Message delivered to a single chat session.
+pub struct MsgChatMessage(pub shared::SendMessageResponseBody);
+
+impl Message for MsgChatMessage {
+    type Result = ();
+}
+
+/// Registers a chat session, returns its id.
+pub struct MsgConnect(pub Recipient<MsgChatMessage>);
+
+impl Message for MsgConnect {
+    type Result = usize;
+}
+
+pub struct MsgDisconnect(pub usize);
+
+impl Message for MsgDisconnect {
+    type Result = ();
+}
+
+pub struct MsgBroadcast(pub shared::SendMessageResponseBody);
+
+impl Message for MsgBroadcast {
+    type Result = ();
+}
+
+// ---- Handlers ----
+
+impl Handler<MsgConnect> for ChatActor {
+    type Result = usize;
+
+    fn handle(&mut self, msg: MsgConnect, _: &mut Context<Self>) -> Self::Result {
+        let id = self.next_session_id;
+        self.next_session_id += 1;
+        self.sessions.insert(id, msg.0);
+        id
+    }
+}
+
+impl Handler<MsgDisconnect> for ChatActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MsgDisconnect, _: &mut Context<Self>) -> Self::Result {
+        self.sessions.remove(&msg.0);
+    }
+}
+
+impl Handler<MsgBroadcast> for ChatActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MsgBroadcast, _: &mut Context<Self>) -> Self::Result {
+        // Sessions with a closed mailbox are gone, drop them.
+        self.sessions.retain(|_, session| {
+            !matches!(
+                session.do_send(MsgChatMessage(msg.0.clone())),
+                Err(SendError::Closed(_))
+            )
+        });
+    }
+}
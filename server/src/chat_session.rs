@@ -0,0 +1,158 @@
+use actix::prelude::*;
+use actix_web_actors::ws;
+use futures::future::{self, Either};
+use std::time::{Duration, Instant};
+use tracing::error;
+
+use crate::chat_actor::{ChatActor, MsgBroadcast, MsgChatMessage, MsgConnect, MsgDisconnect};
+use crate::count_actor::{CountActor, MsgIncrement, MESSAGES_COUNTER};
+use crate::metrics;
+use crate::rate_limiter::{MsgAcquire, RateLimitActor};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// ---- Actor ----
+
+/// WebSocket connection of a single chat client.
+pub struct ChatSession {
+    id: usize,
+    last_heartbeat: Instant,
+    chat_actor: Addr<ChatActor>,
+    count_actor: Addr<CountActor>,
+    // Acquired for every message, `None` without rate limiting.
+    rate_limit: Option<(Addr<RateLimitActor>, MsgAcquire)>,
+}
+
+impl ChatSession {
+    pub fn new(
+        chat_actor: Addr<ChatActor>,
+        count_actor: Addr<CountActor>,
+        rate_limit: Option<(Addr<RateLimitActor>, MsgAcquire)>,
+    ) -> Self {
+        Self {
+            id: 0,
+            last_heartbeat: Instant::now(),
+            chat_actor,
+            count_actor,
+            rate_limit,
+        }
+    }
+
+    fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |session, ctx| {
+            if Instant::now().duration_since(session.last_heartbeat) > CLIENT_TIMEOUT {
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+
+    // Messages over the limit are answered with the rate limit error.
+    fn acquire(&self, text: String, ctx: &mut ws::WebsocketContext<Self>) {
+        let acquire = match &self.rate_limit {
+            Some((actor, msg)) => Either::Left(actor.send(msg.clone())),
+            None => Either::Right(future::ok(Ok(()))),
+        };
+        acquire
+            .into_actor(self)
+            .map(move |result, session, ctx| match result {
+                Ok(Ok(())) => session.send_message(text, ctx),
+                Ok(Err(error)) => {
+                    metrics::inc_rate_limited_requests(Some("/api/chat"));
+                    ctx.text(error.to_string())
+                }
+                Err(_) => ctx.stop(),
+            })
+            .wait(ctx);
+    }
+
+    // Messages sent over the socket get their ordinal number the same way
+    // as messages sent through `/api/send-message`.
+    fn send_message(&self, text: String, ctx: &mut ws::WebsocketContext<Self>) {
+        let chat_actor = self.chat_actor.clone();
+        metrics::count_actor_mailbox(
+            self.count_actor
+                .send(MsgIncrement::new(MESSAGES_COUNTER, 1)),
+        )
+        .into_actor(self)
+        .map(move |result, _, ctx| match result {
+            Ok(Ok(ordinal_number)) => {
+                chat_actor.do_send(MsgBroadcast(shared::SendMessageResponseBody {
+                    ordinal_number,
+                    text,
+                }))
+            }
+            Ok(Err(error)) => ctx.text(error.to_string()),
+            Err(_) => ctx.stop(),
+        })
+        .wait(ctx);
+    }
+}
+
+impl Actor for ChatSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.heartbeat(ctx);
+        self.chat_actor
+            .send(MsgConnect(ctx.address().recipient()))
+            .into_actor(self)
+            .map(|result, session, ctx| match result {
+                Ok(id) => session.id = id,
+                Err(_) => ctx.stop(),
+            })
+            .wait(ctx);
+    }
+
+    fn stopping(&mut self, _: &mut Self::Context) -> Running {
+        self.chat_actor.do_send(MsgDisconnect(self.id));
+        Running::Stop
+    }
+}
+
+// ---- Handlers ----
+
+impl Handler<MsgChatMessage> for ChatSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: MsgChatMessage, ctx: &mut Self::Context) -> Self::Result {
+        match serde_json::to_string(&msg.0) {
+            Ok(json) => ctx.text(json),
+            Err(error) => error!(%error, "chat message serialization failed"),
+        }
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for ChatSession {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(_) => {
+                ctx.stop();
+                return;
+            }
+        };
+        match msg {
+            ws::Message::Ping(bytes) => {
+                self.last_heartbeat = Instant::now();
+                ctx.pong(&bytes);
+            }
+            ws::Message::Pong(_) => {
+                self.last_heartbeat = Instant::now();
+            }
+            ws::Message::Text(text) => {
+                match serde_json::from_str::<shared::SendMessageRequestBody>(&text) {
+                    Ok(request_data) => self.acquire(request_data.text, ctx),
+                    Err(error) => ctx.text(format!("Invalid message: {}", error)),
+                }
+            }
+            ws::Message::Close(reason) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            ws::Message::Binary(_) | ws::Message::Continuation(_) | ws::Message::Nop => (),
+        }
+    }
+}
@@ -0,0 +1,1052 @@
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::Method;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use structopt::StructOpt;
+use tracing_subscriber::EnvFilter;
+
+use crate::count_store::CountStore;
+use crate::user_store::UserStore;
+
+const DEFAULT_CONFIG_FILE: &str = "./server.toml";
+
+// HS256 keys shorter than the hash output are easier to brute force.
+const MIN_JWT_SECRET_LEN: usize = 32;
+
+// ---- Config ----
+
+/// Server configuration, layered from defaults, the TOML file,
+/// env variables and CLI flags (later layers win).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub pkg_folder: PathBuf,
+    pub index_file: PathBuf,
+    /// Paths served with `index_file`, `*` matches one segment, `**` any number.
+    pub client_routes: Vec<String>,
+    /// How long in-flight requests may take to finish after SIGTERM.
+    pub shutdown_timeout_secs: u64,
+    pub limits: Limits,
+    pub form: FormConfig,
+    pub uploads: UploadsConfig,
+    pub count_store: CountStoreConfig,
+    pub jobs: JobsConfig,
+    pub delayed_response: DelayedResponseConfig,
+    pub logging: LoggingConfig,
+    pub rate_limit: RateLimitConfig,
+    pub chaos: ChaosConfig,
+    pub cors: CorsConfig,
+    pub auth: AuthConfig,
+    pub users: UsersConfig,
+    pub tls: Option<TlsConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind_address: IpAddr::from([0, 0, 0, 0]),
+            port: 3333,
+            pkg_folder: "./client/pkg".into(),
+            index_file: "./client/index.html".into(),
+            client_routes: vec!["/**".into()],
+            shutdown_timeout_secs: 30,
+            limits: Limits::default(),
+            form: FormConfig::default(),
+            uploads: UploadsConfig::default(),
+            count_store: CountStoreConfig::default(),
+            jobs: JobsConfig::default(),
+            delayed_response: DelayedResponseConfig::default(),
+            logging: LoggingConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            chaos: ChaosConfig::default(),
+            cors: CorsConfig::default(),
+            auth: AuthConfig::default(),
+            users: UsersConfig::default(),
+            tls: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub json_payload_bytes: usize,
+    pub max_batch_size: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            json_payload_bytes: 32 * 1024,
+            max_batch_size: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StoreBackend {
+    Memory,
+    File,
+}
+
+impl FromStr for StoreBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memory" => Ok(Self::Memory),
+            "file" => Ok(Self::File),
+            _ => Err("expected 'memory' or 'file'".into()),
+        }
+    }
+}
+
+/// Limits of `POST /api/form`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FormConfig {
+    /// Text fields are kept in memory.
+    pub max_field_bytes: usize,
+    /// File fields are streamed into temp files.
+    pub max_file_bytes: u64,
+    pub max_total_bytes: u64,
+    /// Accepted content types of file fields, e.g. `text/plain` or `text/*`.
+    pub file_content_types: Vec<String>,
+    /// Directory of the temp files, the system temp directory if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl Default for FormConfig {
+    fn default() -> Self {
+        Self {
+            max_field_bytes: 64 * 1024,
+            max_file_bytes: 10 * 1024 * 1024,
+            max_total_bytes: 11 * 1024 * 1024,
+            file_content_types: vec!["text/plain".into()],
+            temp_dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadsConfig {
+    /// Files uploaded through `/api/form`, created if missing.
+    pub dir: PathBuf,
+}
+
+impl Default for UploadsConfig {
+    fn default() -> Self {
+        Self {
+            dir: "./uploads".into(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CountStoreConfig {
+    pub backend: StoreBackend,
+    pub path: PathBuf,
+    pub snapshot_interval_ms: u64,
+}
+
+impl Default for CountStoreConfig {
+    fn default() -> Self {
+        Self {
+            backend: StoreBackend::Memory,
+            path: "./count.txt".into(),
+            snapshot_interval_ms: 5000,
+        }
+    }
+}
+
+impl CountStoreConfig {
+    pub fn store(&self) -> CountStore {
+        match self.backend {
+            StoreBackend::Memory => CountStore::Memory,
+            StoreBackend::File => CountStore::File(self.path.clone()),
+        }
+    }
+
+    pub fn snapshot_interval(&self) -> Duration {
+        Duration::from_millis(self.snapshot_interval_ms)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConfig {
+    pub expiry_secs: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        Self { expiry_secs: 300 }
+    }
+}
+
+impl JobsConfig {
+    pub fn expiry(&self) -> Duration {
+        Duration::from_secs(self.expiry_secs)
+    }
+}
+
+/// Bounds of `/api/delayed-response/{delay}?jitter=`,
+/// the delay bounds also apply to batch conversions and jobs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DelayedResponseConfig {
+    pub min_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub max_jitter_ms: u64,
+}
+
+impl Default for DelayedResponseConfig {
+    fn default() -> Self {
+        Self {
+            min_delay_ms: 0,
+            max_delay_ms: 60_000,
+            max_jitter_ms: 10_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => Err("expected 'pretty' or 'json'".into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+    pub format: LogFormat,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".into(),
+            format: LogFormat::Pretty,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Reverse proxies whose `Forwarded` or `X-Forwarded-For` client addresses are used,
+    /// other clients are limited by their peer address.
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Shared by all routes without their own limit.
+    pub default: BucketConfig,
+    /// Limits by request path prefix, the longest matching prefix wins.
+    pub routes: BTreeMap<String, BucketConfig>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let mut routes = BTreeMap::new();
+        routes.insert(
+            "/api/send-message".into(),
+            BucketConfig {
+                per_second: 1.,
+                burst: 5,
+            },
+        );
+        routes.insert(
+            "/api/delayed-response/".into(),
+            BucketConfig {
+                per_second: 0.5,
+                burst: 2,
+            },
+        );
+        Self {
+            enabled: true,
+            trusted_proxies: Vec::new(),
+            default: BucketConfig {
+                per_second: 20.,
+                burst: 50,
+            },
+            routes,
+        }
+    }
+}
+
+/// Token bucket refilled by `per_second` tokens up to `burst` tokens.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosConfig {
+    /// Whether faults are injected at start, can be toggled through the admin endpoint.
+    pub enabled: bool,
+    /// Serves `GET` and `PUT /admin/chaos`.
+    pub admin_endpoint: bool,
+    /// Fixed seed for reproducible faults, random if not set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Rules by request path prefix, the longest matching prefix wins.
+    pub routes: BTreeMap<String, ChaosRule>,
+}
+
+/// Probabilities (0.0 - 1.0) of the faults injected into a request.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChaosRule {
+    /// Random extra latency up to `latency_ms`.
+    pub latency_ms: u64,
+    pub latency_probability: f64,
+    /// Random `500`, `502`, `503` or `504` instead of calling the handler.
+    pub error_probability: f64,
+    /// Half of the first body chunk, then the connection is closed.
+    pub truncate_probability: f64,
+    /// Closed connection instead of calling the handler.
+    pub reset_probability: f64,
+}
+
+impl ChaosRule {
+    pub fn validate(&self) -> Result<(), String> {
+        let probabilities = [
+            self.latency_probability,
+            self.error_probability,
+            self.truncate_probability,
+            self.reset_probability,
+        ];
+        if probabilities.iter().any(|p| !(0. ..=1.).contains(p)) {
+            return Err("probabilities have to be between 0 and 1".into());
+        }
+        if self.error_probability + self.truncate_probability + self.reset_probability > 1. {
+            return Err(
+                "error, truncate and reset probabilities have to sum up to at most 1".into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Rules by request path prefix, the longest matching prefix wins.
+    /// Paths without a rule get no CORS headers.
+    pub scopes: BTreeMap<String, CorsRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsRule {
+    /// E.g. `https://example.com`, `*` allows any origin.
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    /// Request headers besides the CORS-safelisted ones.
+    pub allowed_headers: Vec<String>,
+    /// Response headers readable by scripts besides the CORS-safelisted ones.
+    pub expose_headers: Vec<String>,
+    /// Lets browsers send cookies, can't be combined with the `*` origin.
+    pub allow_credentials: bool,
+    /// How long browsers may cache preflight responses.
+    pub max_age_secs: u64,
+}
+
+impl Default for CorsRule {
+    fn default() -> Self {
+        Self {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec!["GET".into(), "POST".into()],
+            allowed_headers: vec!["authorization".into(), "content-type".into()],
+            expose_headers: Vec::new(),
+            allow_credentials: false,
+            max_age_secs: 600,
+        }
+    }
+}
+
+impl CorsRule {
+    fn validate(&self) -> Result<(), String> {
+        if self.allowed_origins.is_empty() {
+            return Err("allowed_origins must not be empty".into());
+        }
+        for origin in &self.allowed_origins {
+            let valid = origin == "*"
+                || ((origin.starts_with("http://") || origin.starts_with("https://"))
+                    && !origin.ends_with('/')
+                    && HeaderValue::from_str(origin).is_ok());
+            if !valid {
+                return Err(format!(
+                    "invalid origin '{}', use e.g. 'https://example.com' or '*'",
+                    origin
+                ));
+            }
+        }
+        if self.allow_credentials && self.allowed_origins.iter().any(|origin| origin == "*") {
+            return Err("allow_credentials can't be combined with the '*' origin".into());
+        }
+        if self.allowed_methods.is_empty() {
+            return Err("allowed_methods must not be empty".into());
+        }
+        if let Some(method) = self
+            .allowed_methods
+            .iter()
+            .find(|method| Method::from_bytes(method.as_bytes()).is_err())
+        {
+            return Err(format!("invalid method '{}'", method));
+        }
+        if let Some(name) = self
+            .allowed_headers
+            .iter()
+            .chain(&self.expose_headers)
+            .find(|name| HeaderName::from_bytes(name.as_bytes()).is_err())
+        {
+            return Err(format!("invalid header name '{}'", name));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Requires credentials for `/api` and `/admin` routes.
+    pub enabled: bool,
+    /// HS256 secret of bearer tokens, tokens are disabled without it.
+    /// Never printed by `--print-config`.
+    #[serde(skip_serializing)]
+    pub jwt_secret: Option<String>,
+    pub token_ttl_secs: u64,
+    pub api_keys: Vec<ApiKeyConfig>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            jwt_secret: None,
+            token_ttl_secs: 3600,
+            api_keys: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+    pub name: String,
+    /// Never printed by `--print-config`.
+    #[serde(skip_serializing)]
+    pub key: String,
+    /// E.g. `counters:read`, `counters:*` or `*`.
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsersConfig {
+    pub backend: StoreBackend,
+    pub path: PathBuf,
+    pub session_ttl_secs: u64,
+    /// How often recorded messages are saved, accounts are saved right away.
+    pub save_interval_ms: u64,
+    /// Scopes of logged-in users.
+    pub scopes: Vec<String>,
+}
+
+impl Default for UsersConfig {
+    fn default() -> Self {
+        Self {
+            backend: StoreBackend::Memory,
+            path: "./users.json".into(),
+            session_ttl_secs: 24 * 60 * 60,
+            save_interval_ms: 1000,
+            scopes: vec![
+                "messages:*".into(),
+                "counters:read".into(),
+                "form:write".into(),
+                "files:*".into(),
+            ],
+        }
+    }
+}
+
+impl UsersConfig {
+    pub fn store(&self) -> UserStore {
+        match self.backend {
+            StoreBackend::Memory => UserStore::Memory,
+            StoreBackend::File => UserStore::File(self.path.clone()),
+        }
+    }
+
+    pub fn session_ttl(&self) -> Duration {
+        Duration::from_secs(self.session_ttl_secs)
+    }
+
+    pub fn save_interval(&self) -> Duration {
+        Duration::from_millis(self.save_interval_ms)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// Plain HTTP port redirecting to HTTPS on `port`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_port: Option<u16>,
+}
+
+// ---- CLI ----
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "server", about = "Actix server for the Seed client.")]
+pub struct Cli {
+    /// TOML config file [default: ./server.toml if it exists] [env: CONFIG]
+    #[structopt(long, short)]
+    config: Option<PathBuf>,
+    /// Print the resolved configuration as TOML and exit
+    #[structopt(long)]
+    pub print_config: bool,
+    /// [env: BIND_ADDRESS]
+    #[structopt(long)]
+    bind_address: Option<IpAddr>,
+    /// [env: PORT]
+    #[structopt(long, short)]
+    port: Option<u16>,
+    /// Folder with the client wasm bundle [env: PKG_FOLDER]
+    #[structopt(long)]
+    pkg_folder: Option<PathBuf>,
+    /// [env: INDEX]
+    #[structopt(long)]
+    index_file: Option<PathBuf>,
+    /// [env: LOG_LEVEL]
+    #[structopt(long)]
+    log_level: Option<String>,
+    /// `pretty` or `json` [env: LOG_FORMAT]
+    #[structopt(long)]
+    log_format: Option<LogFormat>,
+    /// Certificate chain in PEM, enables HTTPS together with `--tls-key` [env: TLS_CERT]
+    #[structopt(long)]
+    tls_cert: Option<PathBuf>,
+    /// Private key in PEM [env: TLS_KEY]
+    #[structopt(long)]
+    tls_key: Option<PathBuf>,
+    /// Plain HTTP port redirecting to HTTPS [env: TLS_REDIRECT_PORT]
+    #[structopt(long)]
+    tls_redirect_port: Option<u16>,
+}
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Env(&'static str, String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read(path, error) => write!(f, "can't read '{}': {}", path.display(), error),
+            Self::Parse(path, error) => write!(f, "invalid '{}': {}", path.display(), error),
+            Self::Env(name, reason) => write!(f, "invalid env variable {}: {}", name, reason),
+            Self::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+// ---- Loading ----
+
+impl Config {
+    pub fn load(cli: &Cli) -> Result<Self, ConfigError> {
+        Self::from_file(cli)?.layer(cli, |name| env::var(name))
+    }
+
+    // Env variables from `var` and then CLI flags over the file config.
+    fn layer(
+        mut self,
+        cli: &Cli,
+        var: impl Fn(&str) -> Result<String, env::VarError>,
+    ) -> Result<Self, ConfigError> {
+        self.apply_env(&var)?;
+        self.apply_cli(cli);
+        self.validate()?;
+        Ok(self)
+    }
+
+    fn from_file(cli: &Cli) -> Result<Self, ConfigError> {
+        let (path, required) = match (&cli.config, env::var_os("CONFIG")) {
+            (Some(path), _) => (path.clone(), true),
+            (None, Some(path)) => (path.into(), true),
+            (None, None) => (DEFAULT_CONFIG_FILE.into(), false),
+        };
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                toml::from_str(&content).map_err(|error| ConfigError::Parse(path, error))
+            }
+            Err(error) if !required && error.kind() == io::ErrorKind::NotFound => {
+                Ok(Self::default())
+            }
+            Err(error) => Err(ConfigError::Read(path, error)),
+        }
+    }
+
+    fn apply_env(
+        &mut self,
+        var: &impl Fn(&str) -> Result<String, env::VarError>,
+    ) -> Result<(), ConfigError> {
+        if let Some(bind_address) = parse_env(var, "BIND_ADDRESS")? {
+            self.bind_address = bind_address;
+        }
+        if let Some(port) = parse_env(var, "PORT")? {
+            self.port = port;
+        }
+        if let Some(pkg_folder) = parse_env(var, "PKG_FOLDER")? {
+            self.pkg_folder = pkg_folder;
+        }
+        if let Some(index_file) = parse_env(var, "INDEX")? {
+            self.index_file = index_file;
+        }
+        if let Some(shutdown_timeout) = parse_env(var, "SHUTDOWN_TIMEOUT_SECS")? {
+            self.shutdown_timeout_secs = shutdown_timeout;
+        }
+        if let Some(backend) = parse_env(var, "COUNT_STORE")? {
+            self.count_store.backend = backend;
+        }
+        if let Some(path) = parse_env(var, "COUNT_STORE_PATH")? {
+            self.count_store.path = path;
+        }
+        if let Some(interval) = parse_env(var, "COUNT_SNAPSHOT_INTERVAL_MS")? {
+            self.count_store.snapshot_interval_ms = interval;
+        }
+        if let Some(expiry) = parse_env(var, "JOB_EXPIRY_SECS")? {
+            self.jobs.expiry_secs = expiry;
+        }
+        if let Some(min_delay) = parse_env(var, "DELAY_MIN_MS")? {
+            self.delayed_response.min_delay_ms = min_delay;
+        }
+        if let Some(max_delay) = parse_env(var, "DELAY_MAX_MS")? {
+            self.delayed_response.max_delay_ms = max_delay;
+        }
+        if let Some(level) = parse_env(var, "LOG_LEVEL")? {
+            self.logging.level = level;
+        }
+        if let Some(format) = parse_env(var, "LOG_FORMAT")? {
+            self.logging.format = format;
+        }
+        if let Some(enabled) = parse_env(var, "RATE_LIMIT_ENABLED")? {
+            self.rate_limit.enabled = enabled;
+        }
+        if let Some(per_second) = parse_env(var, "RATE_LIMIT_PER_SECOND")? {
+            self.rate_limit.default.per_second = per_second;
+        }
+        if let Some(burst) = parse_env(var, "RATE_LIMIT_BURST")? {
+            self.rate_limit.default.burst = burst;
+        }
+        if let Some(enabled) = parse_env(var, "AUTH_ENABLED")? {
+            self.auth.enabled = enabled;
+        }
+        if let Some(secret) = parse_env(var, "AUTH_JWT_SECRET")? {
+            self.auth.jwt_secret = Some(secret);
+        }
+        if let Some(ttl) = parse_env(var, "AUTH_TOKEN_TTL_SECS")? {
+            self.auth.token_ttl_secs = ttl;
+        }
+        if let Some(backend) = parse_env(var, "USER_STORE")? {
+            self.users.backend = backend;
+        }
+        if let Some(path) = parse_env(var, "USER_STORE_PATH")? {
+            self.users.path = path;
+        }
+        if let Some(dir) = parse_env(var, "UPLOADS_DIR")? {
+            self.uploads.dir = dir;
+        }
+        self.apply_tls(parse_env(var, "TLS_CERT")?, parse_env(var, "TLS_KEY")?);
+        if let Some(redirect_port) = parse_env(var, "TLS_REDIRECT_PORT")? {
+            self.apply_tls_redirect_port(redirect_port);
+        }
+        Ok(())
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(bind_address) = cli.bind_address {
+            self.bind_address = bind_address;
+        }
+        if let Some(port) = cli.port {
+            self.port = port;
+        }
+        if let Some(pkg_folder) = &cli.pkg_folder {
+            self.pkg_folder = pkg_folder.clone();
+        }
+        if let Some(index_file) = &cli.index_file {
+            self.index_file = index_file.clone();
+        }
+        if let Some(level) = &cli.log_level {
+            self.logging.level = level.clone();
+        }
+        if let Some(format) = cli.log_format {
+            self.logging.format = format;
+        }
+        self.apply_tls(cli.tls_cert.clone(), cli.tls_key.clone());
+        if let Some(redirect_port) = cli.tls_redirect_port {
+            self.apply_tls_redirect_port(redirect_port);
+        }
+    }
+
+    // A single path only overrides its half of an already configured pair.
+    fn apply_tls(&mut self, cert: Option<PathBuf>, key: Option<PathBuf>) {
+        if cert.is_none() && key.is_none() {
+            return;
+        }
+        let tls = self.tls.get_or_insert_with(TlsConfig::default);
+        if let Some(cert) = cert {
+            tls.cert = cert;
+        }
+        if let Some(key) = key {
+            tls.key = key;
+        }
+    }
+
+    fn apply_tls_redirect_port(&mut self, redirect_port: u16) {
+        self.tls
+            .get_or_insert_with(TlsConfig::default)
+            .redirect_port = Some(redirect_port);
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |reason: &str| Err(ConfigError::Invalid(reason.to_owned()));
+
+        if let Some(pattern) = self
+            .client_routes
+            .iter()
+            .find(|pattern| !pattern.starts_with('/'))
+        {
+            return Err(ConfigError::Invalid(format!(
+                "client_routes '{}' has to start with '/'",
+                pattern
+            )));
+        }
+        if self.limits.json_payload_bytes == 0 {
+            return invalid("limits.json_payload_bytes has to be greater than 0");
+        }
+        if self.limits.max_batch_size == 0 {
+            return invalid("limits.max_batch_size has to be greater than 0");
+        }
+        if self.form.max_field_bytes == 0
+            || self.form.max_file_bytes == 0
+            || self.form.max_total_bytes == 0
+        {
+            return invalid("form limits have to be greater than 0");
+        }
+        if let Some(content_type) = self
+            .form
+            .file_content_types
+            .iter()
+            .find(|content_type| content_type.parse::<mime::Mime>().is_err())
+        {
+            return Err(ConfigError::Invalid(format!(
+                "invalid form.file_content_types '{}'",
+                content_type
+            )));
+        }
+        if let Some(temp_dir) = &self.form.temp_dir {
+            if !temp_dir.is_dir() {
+                return Err(ConfigError::Invalid(format!(
+                    "form.temp_dir '{}' is not a directory",
+                    temp_dir.display()
+                )));
+            }
+        }
+        check_parent_dir("uploads.dir", &self.uploads.dir)?;
+        if self.count_store.snapshot_interval_ms == 0 {
+            return invalid("count_store.snapshot_interval_ms has to be greater than 0");
+        }
+        if self.count_store.backend == StoreBackend::File {
+            check_parent_dir("count_store.path", &self.count_store.path)?;
+        }
+        if self.jobs.expiry_secs == 0 {
+            return invalid("jobs.expiry_secs has to be greater than 0");
+        }
+        if self.delayed_response.min_delay_ms > self.delayed_response.max_delay_ms {
+            return invalid("delayed_response.min_delay_ms has to be at most max_delay_ms");
+        }
+        if let Err(error) = EnvFilter::try_new(&self.logging.level) {
+            return Err(ConfigError::Invalid(format!(
+                "invalid logging.level '{}': {}",
+                self.logging.level, error
+            )));
+        }
+        check_bucket("rate_limit.default", &self.rate_limit.default)?;
+        for (prefix, limit) in &self.rate_limit.routes {
+            if !prefix.starts_with('/') {
+                return Err(ConfigError::Invalid(format!(
+                    "rate_limit.routes '{}' has to start with '/'",
+                    prefix
+                )));
+            }
+            check_bucket(&format!("rate_limit.routes '{}'", prefix), limit)?;
+        }
+        for (prefix, rule) in &self.chaos.routes {
+            if let Err(reason) = rule.validate() {
+                return Err(ConfigError::Invalid(format!(
+                    "chaos.routes '{}': {}",
+                    prefix, reason
+                )));
+            }
+        }
+        for (prefix, rule) in &self.cors.scopes {
+            if !prefix.starts_with('/') {
+                return Err(ConfigError::Invalid(format!(
+                    "cors.scopes '{}' has to start with '/'",
+                    prefix
+                )));
+            }
+            if let Err(reason) = rule.validate() {
+                return Err(ConfigError::Invalid(format!(
+                    "cors.scopes '{}': {}",
+                    prefix, reason
+                )));
+            }
+        }
+        if let Some(secret) = &self.auth.jwt_secret {
+            if secret.len() < MIN_JWT_SECRET_LEN {
+                return Err(ConfigError::Invalid(format!(
+                    "auth.jwt_secret has to be at least {} bytes long",
+                    MIN_JWT_SECRET_LEN
+                )));
+            }
+        }
+        if self.auth.token_ttl_secs == 0 {
+            return invalid("auth.token_ttl_secs has to be greater than 0");
+        }
+        if self.users.backend == StoreBackend::File {
+            check_parent_dir("users.path", &self.users.path)?;
+        }
+        if self.users.session_ttl_secs == 0 {
+            return invalid("users.session_ttl_secs has to be greater than 0");
+        }
+        if self.users.save_interval_ms == 0 {
+            return invalid("users.save_interval_ms has to be greater than 0");
+        }
+        if let Some(tls) = &self.tls {
+            check_file("tls.cert", &tls.cert)?;
+            check_file("tls.key", &tls.key)?;
+            if tls.redirect_port == Some(self.port) {
+                return invalid("tls.redirect_port has to differ from port");
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string_pretty(self)
+    }
+}
+
+fn parse_env<T: FromStr>(
+    var: &impl Fn(&str) -> Result<String, env::VarError>,
+    name: &'static str,
+) -> Result<Option<T>, ConfigError>
+where
+    T::Err: fmt::Display,
+{
+    match var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|error| ConfigError::Env(name, format!("'{}': {}", value, error))),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(error) => Err(ConfigError::Env(name, error.to_string())),
+    }
+}
+
+fn check_file(name: &str, path: &Path) -> Result<(), ConfigError> {
+    if path.is_file() {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid(format!(
+            "{} '{}' is not a file",
+            name,
+            path.display()
+        )))
+    }
+}
+
+fn check_bucket(name: &str, limit: &BucketConfig) -> Result<(), ConfigError> {
+    if !limit.per_second.is_finite() || limit.per_second <= 0. {
+        Err(ConfigError::Invalid(format!(
+            "{} per_second has to be greater than 0",
+            name
+        )))
+    } else if limit.burst == 0 {
+        Err(ConfigError::Invalid(format!(
+            "{} burst has to be greater than 0",
+            name
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+fn check_parent_dir(name: &str, path: &Path) -> Result<(), ConfigError> {
+    match path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) if !dir.is_dir() => Err(ConfigError::Invalid(format!(
+            "{} '{}' is not in an existing directory",
+            name,
+            path.display()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(toml: &str, vars: &[(&str, &str)], args: &[&str]) -> Result<Config, ConfigError> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        let cli = Cli::from_iter(std::iter::once("server").chain(args.iter().copied()));
+        toml::from_str::<Config>(toml)
+            .expect("parse config")
+            .layer(&cli, |name| {
+                vars.get(name)
+                    .map(|value| value.to_string())
+                    .ok_or(env::VarError::NotPresent)
+            })
+    }
+
+    fn invalid(toml: &str) -> String {
+        match load(toml, &[], &[]) {
+            Err(ConfigError::Invalid(reason)) => reason,
+            other => panic!("expected an invalid config, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn env_overrides_file_and_cli_overrides_env() {
+        let toml = r#"
+            port = 4000
+            [logging]
+            level = "debug"
+            [jobs]
+            expiry_secs = 10
+        "#;
+        let vars = [("PORT", "5000"), ("LOG_LEVEL", "warn")];
+        let config = load(toml, &vars, &["--log-level", "error"]).unwrap();
+
+        assert_eq!(config.port, 5000);
+        assert_eq!(config.logging.level, "error");
+        assert_eq!(config.jobs.expiry_secs, 10);
+        assert_eq!(config.shutdown_timeout_secs, 30);
+    }
+
+    #[test]
+    fn tls_paths_override_their_half_of_the_pair() {
+        let toml = r#"
+            [tls]
+            cert = "Cargo.toml"
+            key = "Cargo.toml"
+        "#;
+        let config = load(toml, &[("TLS_KEY", "src/main.rs")], &[]).unwrap();
+        let tls = config.tls.unwrap();
+        assert_eq!(tls.cert, Path::new("Cargo.toml"));
+        assert_eq!(tls.key, Path::new("src/main.rs"));
+    }
+
+    #[test]
+    fn secrets_are_not_printed() {
+        let toml = r#"
+            [auth]
+            jwt_secret = "0123456789abcdef0123456789abcdef"
+            [[auth.api_keys]]
+            name = "ci"
+            key = "secret-api-key"
+            scopes = ["*"]
+        "#;
+        let printed = load(toml, &[], &[]).unwrap().to_toml().unwrap();
+        assert!(printed.contains("[[auth.api_keys]]"));
+        assert!(!printed.contains("0123456789abcdef"));
+        assert!(!printed.contains("secret-api-key"));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(toml::from_str::<Config>("prot = 4000").is_err());
+        assert!(toml::from_str::<Config>("[jobs]\nexpiry = 10").is_err());
+        assert!(toml::from_str::<Config>(
+            "[rate_limit.default]\nper_second = 1\nburst = 1\nrate = 1"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn invalid_env_variables_are_rejected() {
+        assert!(matches!(
+            load("", &[("PORT", "http")], &[]),
+            Err(ConfigError::Env("PORT", _))
+        ));
+        assert!(matches!(
+            load("", &[("LOG_FORMAT", "xml")], &[]),
+            Err(ConfigError::Env("LOG_FORMAT", _))
+        ));
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert!(load("", &[], &[]).is_ok());
+        assert!(invalid("client_routes = [\"app\"]").contains("client_routes"));
+        assert!(invalid("[jobs]\nexpiry_secs = 0").contains("jobs.expiry_secs"));
+        assert!(
+            invalid("[delayed_response]\nmin_delay_ms = 2\nmax_delay_ms = 1")
+                .contains("min_delay_ms")
+        );
+        assert!(
+            invalid("[rate_limit.routes.\"/api/\"]\nper_second = 0\nburst = 1")
+                .contains("per_second")
+        );
+        assert!(invalid("[chaos.routes.\"/api/\"]\nerror_probability = 2").contains("chaos"));
+        assert!(invalid(
+            "[cors.scopes.\"/api/\"]\nallowed_origins = [\"*\"]\nallow_credentials = true"
+        )
+        .contains("allow_credentials"));
+        assert!(invalid("[auth]\njwt_secret = \"short\"").contains("jwt_secret"));
+        assert!(
+            invalid("[tls]\ncert = \"missing.pem\"\nkey = \"missing.pem\"").contains("tls.cert")
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use actix_web::web::Bytes;
+use futures::stream::{self, Stream};
+use nalgebra::{self as na, Matrix3, Rotation3, UnitQuaternion, Vector3};
+use serde::Serialize;
+use std::f64::consts::PI;
+use std::time::Duration;
+
+use shared::{ConversionProgress, EulerAngles, Quaternion, RotationMatrix};
+
+pub fn to_quaternion(matrix: &RotationMatrix) -> Quaternion {
+    let m = Matrix3::from_row_slice(&matrix.values);
+    let rot_matrix = Rotation3::from_matrix(&m);
+    let q = UnitQuaternion::from_rotation_matrix(&rot_matrix);
+    Quaternion {
+        x: q[0],
+        y: q[1],
+        z: q[2],
+        w: q[3],
+    }
+}
+
+pub fn to_matrix(quaternion: &Quaternion) -> RotationMatrix {
+    let rotation = unit_quaternion(quaternion).to_rotation_matrix();
+    // nalgebra stores matrices column-major, `RotationMatrix` is row-major.
+    let mut values = [0.; 9];
+    values.copy_from_slice(rotation.matrix().transpose().as_slice());
+    RotationMatrix { values }
+}
+
+pub fn to_euler_angles(quaternion: &Quaternion) -> EulerAngles {
+    let (roll, pitch, yaw) = unit_quaternion(quaternion).euler_angles();
+    EulerAngles { roll, pitch, yaw }
+}
+
+fn unit_quaternion(q: &Quaternion) -> UnitQuaternion<f64> {
+    UnitQuaternion::from_quaternion(na::Quaternion::new(q.w, q.x, q.y, q.z))
+}
+
+/// Rotations around the z axis, evenly spread over a full turn.
+pub fn turntable_batch(count: u32) -> Vec<RotationMatrix> {
+    (0..count)
+        .map(|i| {
+            let angle = 2. * PI * f64::from(i) / f64::from(count);
+            let rotation = Rotation3::from_axis_angle(&Vector3::z_axis(), angle);
+            // nalgebra stores matrices column-major, `RotationMatrix` is row-major.
+            let mut values = [0.; 9];
+            values.copy_from_slice(rotation.matrix().transpose().as_slice());
+            RotationMatrix { values }
+        })
+        .collect()
+}
+
+// ---- Progress ----
+
+struct Batch {
+    matrices: std::vec::IntoIter<RotationMatrix>,
+    total: u32,
+    quaternions: Vec<Quaternion>,
+    step_delay: Duration,
+    started: bool,
+}
+
+/// Converts the matrices one by one, waiting `step_delay` before each of them.
+pub fn convert_with_progress(
+    matrices: Vec<RotationMatrix>,
+    step_delay: Duration,
+) -> impl Stream<Item = ConversionProgress> {
+    let batch = Batch {
+        total: matrices.len() as u32,
+        quaternions: Vec::with_capacity(matrices.len()),
+        matrices: matrices.into_iter(),
+        step_delay,
+        started: false,
+    };
+    stream::unfold(Some(batch), |batch| async move {
+        let mut batch = batch?;
+        if !batch.started {
+            batch.started = true;
+            let event = ConversionProgress::Started { total: batch.total };
+            return Some((event, Some(batch)));
+        }
+        match batch.matrices.next() {
+            Some(matrix) => {
+                futures_timer::Delay::new(batch.step_delay).await;
+                batch.quaternions.push(to_quaternion(&matrix));
+                let done = batch.quaternions.len() as u32;
+                let event = ConversionProgress::Progress {
+                    done,
+                    total: batch.total,
+                    percent: (u64::from(done) * 100 / u64::from(batch.total)) as u8,
+                };
+                Some((event, Some(batch)))
+            }
+            None => {
+                let event = ConversionProgress::Completed {
+                    quaternions: batch.quaternions,
+                };
+                Some((event, None))
+            }
+        }
+    })
+}
+
+/// Formats `data` as a Server-Sent Event.
+pub fn sse_event(data: &impl Serialize) -> serde_json::Result<Bytes> {
+    Ok(Bytes::from(format!(
+        "data: {}\n\n",
+        serde_json::to_string(data)?
+    )))
+}
@@ -0,0 +1,416 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::{Error, HttpResponse, ResponseError};
+use futures::future::{self, LocalBoxFuture, Ready};
+use std::fmt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use crate::config::{CorsConfig, CorsRule};
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum CorsError {
+    Origin(String),
+    Method(String),
+    Header(String),
+}
+
+impl fmt::Display for CorsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Origin(origin) => write!(f, "Origin '{}' is not allowed", origin),
+            Self::Method(method) => {
+                write!(f, "Cross-origin method '{}' is not allowed", method)
+            }
+            Self::Header(name) => {
+                write!(f, "Cross-origin header '{}' is not allowed", name)
+            }
+        }
+    }
+}
+
+impl ResponseError for CorsError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::FORBIDDEN
+    }
+}
+
+// ---- Rules ----
+
+impl CorsConfig {
+    // The rule of the longest configured prefix of `path`.
+    fn rule(&self, path: &str) -> Option<&CorsRule> {
+        self.scopes
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, rule)| rule)
+    }
+}
+
+impl CorsRule {
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed == origin)
+    }
+
+    // Credentialed responses can't use the `*` wildcard, the origin is echoed instead.
+    fn allow_origin(&self, origin: &str) -> String {
+        if !self.allow_credentials && self.allowed_origins.iter().any(|allowed| allowed == "*") {
+            "*".to_owned()
+        } else {
+            origin.to_owned()
+        }
+    }
+
+    fn response_headers(&self, origin: &str) -> Vec<(HeaderName, String)> {
+        let mut headers = vec![(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            self.allow_origin(origin),
+        )];
+        if self.allow_credentials {
+            headers.push((header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true".to_owned()));
+        }
+        if !self.expose_headers.is_empty() {
+            headers.push((
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                self.expose_headers.join(", "),
+            ));
+        }
+        headers
+    }
+
+    fn preflight_headers(
+        &self,
+        origin: &str,
+        request_headers: &header::HeaderMap,
+    ) -> Result<Vec<(HeaderName, String)>, CorsError> {
+        if !self.allows_origin(origin) {
+            return Err(CorsError::Origin(origin.to_owned()));
+        }
+        let method = request_headers
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if !self
+            .allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+        {
+            return Err(CorsError::Method(method.to_owned()));
+        }
+        let names = request_headers
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty());
+        for name in names {
+            if !self
+                .allowed_headers
+                .iter()
+                .any(|allowed| allowed.eq_ignore_ascii_case(name))
+            {
+                return Err(CorsError::Header(name.to_owned()));
+            }
+        }
+
+        let mut headers = self.response_headers(origin);
+        headers.push((
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            self.allowed_methods.join(", "),
+        ));
+        if !self.allowed_headers.is_empty() {
+            headers.push((
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                self.allowed_headers.join(", "),
+            ));
+        }
+        headers.push((
+            header::ACCESS_CONTROL_MAX_AGE,
+            self.max_age_secs.to_string(),
+        ));
+        Ok(headers)
+    }
+}
+
+fn is_preflight(req: &ServiceRequest) -> bool {
+    req.method() == Method::OPTIONS
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+fn insert_headers(headers: &mut header::HeaderMap, cors_headers: Vec<(HeaderName, String)>) {
+    for (name, value) in cors_headers {
+        // Configured values are validated on startup.
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(name, value);
+        }
+    }
+}
+
+// ---- Middleware ----
+
+/// Answers CORS preflight requests and adds CORS headers to responses
+/// for the path prefixes in `cors.scopes`.
+/// Requests from other origins aren't rejected, browsers withhold the responses instead,
+/// as same-origin `POST` requests have an `Origin` header too.
+/// The inner middleware turn their errors into responses, so that the CORS headers are added.
+pub struct Cors {
+    config: Rc<CorsConfig>,
+}
+
+impl Cors {
+    pub fn new(config: CorsConfig) -> Self {
+        Self {
+            config: Rc::new(config),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Cors
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(CorsMiddleware {
+            service,
+            config: self.config.clone(),
+        })
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+    config: Rc<CorsConfig>,
+}
+
+impl<S, B> Service for CorsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let rule = match self.config.rule(req.path()) {
+            Some(rule) => rule,
+            None => return Box::pin(self.service.call(req)),
+        };
+        let origin = req
+            .headers()
+            .get(header::ORIGIN)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        if let (Some(origin), true) = (&origin, is_preflight(&req)) {
+            let response = match rule.preflight_headers(origin, req.headers()) {
+                Ok(cors_headers) => {
+                    let mut response = HttpResponse::NoContent().finish();
+                    insert_headers(response.headers_mut(), cors_headers);
+                    req.into_response(response.into_body())
+                }
+                Err(error) => req.error_response(error),
+            };
+            return Box::pin(future::ok(response));
+        }
+
+        let cors_headers = origin
+            .filter(|origin| rule.allows_origin(origin))
+            .map(|origin| rule.response_headers(&origin))
+            .unwrap_or_default();
+        let response = self.service.call(req);
+        Box::pin(async move {
+            let mut response = response.await?;
+            let headers = response.headers_mut();
+            // Also without `Origin`, caches mustn't serve this response to other origins.
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+            insert_headers(headers, cors_headers);
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    const ORIGIN: &str = "https://example.com";
+
+    fn rule(allowed_origins: &[&str]) -> CorsRule {
+        CorsRule {
+            allowed_origins: allowed_origins.iter().map(|&o| o.to_owned()).collect(),
+            ..CorsRule::default()
+        }
+    }
+
+    fn preflight_request(method: &str, headers: &str) -> header::HeaderMap {
+        let mut request_headers = header::HeaderMap::new();
+        request_headers.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_str(method).unwrap(),
+        );
+        request_headers.insert(
+            header::ACCESS_CONTROL_REQUEST_HEADERS,
+            HeaderValue::from_str(headers).unwrap(),
+        );
+        request_headers
+    }
+
+    fn value<'a>(headers: &'a [(HeaderName, String)], name: &HeaderName) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(header_name, _)| header_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn allows_listed_origins_only() {
+        let rule = rule(&[ORIGIN]);
+        assert!(rule.allows_origin(ORIGIN));
+        assert!(!rule.allows_origin("https://evil.example.com"));
+        assert!(!rule.allows_origin("http://example.com"));
+    }
+
+    #[test]
+    fn wildcard_allows_any_origin() {
+        let rule = rule(&["*"]);
+        assert!(rule.allows_origin(ORIGIN));
+        assert!(rule.allows_origin("https://evil.example.com"));
+    }
+
+    #[test]
+    fn preflight_of_allowed_origin() {
+        let headers = rule(&[ORIGIN])
+            .preflight_headers(ORIGIN, &preflight_request("POST", "Content-Type"))
+            .unwrap();
+        assert_eq!(
+            value(&headers, &header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some(ORIGIN)
+        );
+        assert_eq!(
+            value(&headers, &header::ACCESS_CONTROL_ALLOW_METHODS),
+            Some("GET, POST")
+        );
+        assert_eq!(
+            value(&headers, &header::ACCESS_CONTROL_MAX_AGE),
+            Some("600")
+        );
+    }
+
+    #[test]
+    fn preflight_of_rejected_origin() {
+        let result = rule(&[ORIGIN])
+            .preflight_headers("https://evil.example.com", &preflight_request("GET", ""));
+        assert!(matches!(result, Err(CorsError::Origin(_))));
+    }
+
+    #[test]
+    fn preflight_of_rejected_method_and_header() {
+        let rule = rule(&[ORIGIN]);
+        let result = rule.preflight_headers(ORIGIN, &preflight_request("DELETE", ""));
+        assert!(matches!(result, Err(CorsError::Method(_))));
+        let result = rule.preflight_headers(ORIGIN, &preflight_request("GET", "x-secret"));
+        assert!(matches!(result, Err(CorsError::Header(_))));
+    }
+
+    #[test]
+    fn wildcard_preflight() {
+        let headers = rule(&["*"])
+            .preflight_headers(ORIGIN, &preflight_request("GET", ""))
+            .unwrap();
+        assert_eq!(
+            value(&headers, &header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some("*")
+        );
+
+        // Credentialed responses echo the origin instead.
+        let credentialed = CorsRule {
+            allow_credentials: true,
+            ..rule(&["*"])
+        };
+        let headers = credentialed
+            .preflight_headers(ORIGIN, &preflight_request("GET", ""))
+            .unwrap();
+        assert_eq!(
+            value(&headers, &header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some(ORIGIN)
+        );
+    }
+
+    #[actix_rt::test]
+    async fn middleware_headers() {
+        let mut config = CorsConfig::default();
+        config.scopes.insert("/api/".to_owned(), rule(&[ORIGIN]));
+        let mut app = test::init_service(
+            App::new()
+                .wrap(Cors::new(config))
+                .route("/api/ok", web::get().to(HttpResponse::Ok))
+                .route("/other", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/api/ok")
+            .header(header::ORIGIN, ORIGIN)
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(
+            response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+            Some(&HeaderValue::from_static(ORIGIN))
+        );
+
+        let request = test::TestRequest::get()
+            .uri("/api/ok")
+            .header(header::ORIGIN, "https://evil.example.com")
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let request = test::TestRequest::get().uri("/api/ok").to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(
+            response.headers().get(header::VARY),
+            Some(&HeaderValue::from_static("Origin"))
+        );
+
+        let request = test::TestRequest::get().uri("/other").to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert!(!response.headers().contains_key(header::VARY));
+
+        let request = test::TestRequest::with_uri("/api/ok")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "https://evil.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -1,28 +1,320 @@
 use actix::prelude::*;
+use actix_web::{http::StatusCode, ResponseError};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use tracing::{debug, error, Span};
+
+use crate::count_store::{CountStore, Counts};
+use crate::metrics;
+
+/// Counter used by `/api/send-message` for the message ordinal numbers.
+pub const MESSAGES_COUNTER: &str = "messages";
+
+// How many values are reserved in the store ahead of time.
+// After a crash a counter continues from the reserved value,
+// so the same value is never handed out twice.
+const RESERVATION_BLOCK: u64 = 100;
 
 // ---- Actor ----
 
-pub struct CountActor(pub u32);
+struct Counter {
+    value: u64,
+    reserved: u64,
+}
+
+pub struct CountActor {
+    counters: BTreeMap<String, Counter>,
+    store: CountStore,
+    snapshot_interval: Duration,
+}
+
+impl CountActor {
+    pub fn new(store: CountStore, snapshot_interval: Duration) -> io::Result<Self> {
+        let counters = store
+            .load(MESSAGES_COUNTER)?
+            .into_iter()
+            .map(|(name, value)| {
+                metrics::set_counter_value(&name, value);
+                let counter = Counter {
+                    value,
+                    reserved: value,
+                };
+                (name, counter)
+            })
+            .collect();
+        Ok(Self {
+            counters,
+            store,
+            snapshot_interval,
+        })
+    }
+
+    fn reserved_counts(&self) -> Counts {
+        self.counters
+            .iter()
+            .map(|(name, counter)| (name.clone(), counter.reserved))
+            .collect()
+    }
+
+    fn save_reserved(&self) -> io::Result<()> {
+        self.store.save(&self.reserved_counts())
+    }
+
+    // Shrink the reservations down to the current values,
+    // so a crash skips only the values reserved since the last snapshot.
+    fn snapshot(&mut self) -> io::Result<()> {
+        if self
+            .counters
+            .values()
+            .all(|counter| counter.reserved == counter.value)
+        {
+            return Ok(());
+        }
+        self.store.save(
+            &self
+                .counters
+                .iter()
+                .map(|(name, counter)| (name.clone(), counter.value))
+                .collect::<Counts>(),
+        )?;
+        for counter in self.counters.values_mut() {
+            counter.reserved = counter.value;
+        }
+        Ok(())
+    }
+}
 
 impl Actor for CountActor {
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_interval(self.snapshot_interval, |actor, _| {
+            if let Err(error) = actor.snapshot() {
+                error!(%error, "CountActor snapshot failed");
+            }
+        });
+    }
+
+    fn stopping(&mut self, _: &mut Context<Self>) -> Running {
+        if let Err(error) = self.snapshot() {
+            error!(%error, "CountActor final snapshot failed");
+        }
+        Running::Stop
+    }
+}
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum CountError {
+    InvalidName(String),
+    NotFound(String),
+    Overflow(String),
+    Store(io::Error),
+}
+
+impl fmt::Display for CountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidName(name) => write!(
+                f,
+                "Invalid counter name '{}', use only letters, digits, '-', '_' and '.'",
+                name
+            ),
+            Self::NotFound(name) => write!(f, "Counter '{}' not found", name),
+            Self::Overflow(name) => write!(f, "Counter '{}' would overflow", name),
+            Self::Store(error) => write!(f, "Counter store failed: {}", error),
+        }
+    }
+}
+
+impl ResponseError for CountError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidName(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Overflow(_) => StatusCode::CONFLICT,
+            Self::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<io::Error> for CountError {
+    fn from(error: io::Error) -> Self {
+        Self::Store(error)
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), CountError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(CountError::InvalidName(name.to_owned()))
+    }
 }
 
 // ---- Messages ----
 
-pub struct MsgIncrement;
+/// Increments the counter by `step`, the counter is created on demand.
+pub struct MsgIncrement {
+    pub name: String,
+    pub step: u64,
+    /// Span of the sender, the handler runs inside it.
+    pub span: Span,
+}
+
+impl MsgIncrement {
+    pub fn new(name: impl Into<String>, step: u64) -> Self {
+        Self {
+            name: name.into(),
+            step,
+            span: Span::current(),
+        }
+    }
+}
 
 impl Message for MsgIncrement {
-    type Result = u32;
+    type Result = Result<u64, CountError>;
+}
+
+pub struct MsgGet {
+    pub name: String,
+}
+
+impl Message for MsgGet {
+    type Result = Result<u64, CountError>;
+}
+
+/// Writes the current values into the store, e.g. before shutdown.
+pub struct MsgFlush;
+
+impl Message for MsgFlush {
+    type Result = io::Result<()>;
+}
+
+pub struct MsgList;
+
+impl Message for MsgList {
+    type Result = Counts;
+}
+
+pub struct MsgReset {
+    pub name: String,
+}
+
+impl Message for MsgReset {
+    type Result = Result<u64, CountError>;
+}
+
+pub struct MsgDelete {
+    pub name: String,
+}
+
+impl Message for MsgDelete {
+    type Result = Result<(), CountError>;
 }
 
 // ---- Handlers ----
 
 impl Handler<MsgIncrement> for CountActor {
-    type Result = u32;
+    type Result = Result<u64, CountError>;
+
+    fn handle(&mut self, msg: MsgIncrement, _: &mut Context<Self>) -> Self::Result {
+        let _span = msg.span.enter();
+        validate_name(&msg.name)?;
+        let (value, reserved) = self
+            .counters
+            .get(&msg.name)
+            .map_or((0, 0), |counter| (counter.value, counter.reserved));
+        let next = value
+            .checked_add(msg.step)
+            .ok_or_else(|| CountError::Overflow(msg.name.clone()))?;
+
+        let reserved = if next > reserved {
+            let reserved = next.saturating_add(RESERVATION_BLOCK);
+            let mut counts = self.reserved_counts();
+            counts.insert(msg.name.clone(), reserved);
+            self.store.save(&counts)?;
+            debug!(counter = %msg.name, reserved, "counter values reserved");
+            reserved
+        } else {
+            reserved
+        };
+        metrics::set_counter_value(&msg.name, next);
+        self.counters.insert(
+            msg.name,
+            Counter {
+                value: next,
+                reserved,
+            },
+        );
+        Ok(next)
+    }
+}
+
+impl Handler<MsgFlush> for CountActor {
+    type Result = io::Result<()>;
+
+    fn handle(&mut self, _: MsgFlush, _: &mut Context<Self>) -> Self::Result {
+        self.snapshot()
+    }
+}
+
+impl Handler<MsgGet> for CountActor {
+    type Result = Result<u64, CountError>;
+
+    fn handle(&mut self, msg: MsgGet, _: &mut Context<Self>) -> Self::Result {
+        self.counters
+            .get(&msg.name)
+            .map(|counter| counter.value)
+            .ok_or(CountError::NotFound(msg.name))
+    }
+}
+
+impl Handler<MsgList> for CountActor {
+    type Result = MessageResult<MsgList>;
+
+    fn handle(&mut self, _: MsgList, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.counters
+                .iter()
+                .map(|(name, counter)| (name.clone(), counter.value))
+                .collect(),
+        )
+    }
+}
+
+impl Handler<MsgReset> for CountActor {
+    type Result = Result<u64, CountError>;
+
+    fn handle(&mut self, msg: MsgReset, _: &mut Context<Self>) -> Self::Result {
+        let counter = self
+            .counters
+            .get_mut(&msg.name)
+            .ok_or_else(|| CountError::NotFound(msg.name.clone()))?;
+        counter.value = 0;
+        counter.reserved = 0;
+        self.save_reserved()?;
+        metrics::set_counter_value(&msg.name, 0);
+        Ok(0)
+    }
+}
+
+impl Handler<MsgDelete> for CountActor {
+    type Result = Result<(), CountError>;
 
-    fn handle(&mut self, _: MsgIncrement, _: &mut Context<Self>) -> Self::Result {
-        self.0 += 1;
-        self.0
+    fn handle(&mut self, msg: MsgDelete, _: &mut Context<Self>) -> Self::Result {
+        self.counters
+            .remove(&msg.name)
+            .ok_or_else(|| CountError::NotFound(msg.name.clone()))?;
+        self.save_reserved()?;
+        metrics::remove_counter_value(&msg.name);
+        Ok(())
     }
 }
@@ -0,0 +1,85 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Counter values by counter name.
+pub type Counts = BTreeMap<String, u64>;
+
+// ---- Store ----
+
+/// Durable backend for the `CountActor` values.
+#[derive(Debug, Clone)]
+pub enum CountStore {
+    /// Nothing is persisted, the counters are gone on every restart.
+    Memory,
+    /// The values are kept in a plain text file (one `name value` pair per line),
+    /// written atomically and fsynced.
+    File(PathBuf),
+}
+
+impl CountStore {
+    pub fn load(&self, default_counter: &str) -> io::Result<Counts> {
+        match self {
+            Self::Memory => Ok(Counts::new()),
+            Self::File(path) => match fs::read_to_string(path) {
+                Ok(content) => parse_counts(&content, default_counter),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Counts::new()),
+                Err(err) => Err(err),
+            },
+        }
+    }
+
+    pub fn save(&self, counts: &Counts) -> io::Result<()> {
+        match self {
+            Self::Memory => Ok(()),
+            Self::File(path) => {
+                let mut content = String::new();
+                for (name, value) in counts {
+                    content.push_str(&format!("{} {}\n", name, value));
+                }
+                write_atomically(path, content.as_bytes())
+            }
+        }
+    }
+}
+
+// A line with a bare number was written by the single-counter version
+// and belongs to the default counter.
+fn parse_counts(content: &str, default_counter: &str) -> io::Result<Counts> {
+    let invalid_data = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+
+    let mut counts = Counts::new();
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let (name, value) = match line.rsplit_once(' ') {
+            Some((name, value)) => (name.trim(), value),
+            None => (default_counter, line),
+        };
+        counts.insert(name.to_owned(), value.parse().map_err(invalid_data)?);
+    }
+    Ok(counts)
+}
+
+// Write into a sibling temp file, fsync it and rename it over the target,
+// so a crash never leaves a truncated value behind.
+pub fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+
+    // Persist the rename itself.
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
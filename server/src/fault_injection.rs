@@ -0,0 +1,134 @@
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::{error, HttpResponse, ResponseError};
+use futures::stream;
+use rand::Rng;
+use serde::Deserialize;
+use std::fmt;
+use std::time::Duration;
+
+use crate::config::DelayedResponseConfig;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureKind {
+    /// Responds with `status` (default `500`).
+    Status,
+    /// Sends the first half of the body and closes the connection.
+    Partial,
+    /// Closes the connection without a body.
+    Drop,
+}
+
+/// Query of `/api/delayed-response/{delay}`.
+#[derive(Debug, Deserialize)]
+pub struct FaultQuery {
+    /// Adds a random delay up to `jitter` milliseconds.
+    #[serde(default)]
+    pub jitter: u64,
+    pub failure: Option<FailureKind>,
+    pub status: Option<u16>,
+}
+
+pub enum Failure {
+    Status(StatusCode),
+    Partial,
+    Drop,
+}
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum FaultError {
+    DelayOutOfRange { delay: u64, min: u64, max: u64 },
+    JitterTooLarge { jitter: u64, max: u64 },
+    InvalidStatus(u16),
+}
+
+impl fmt::Display for FaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::DelayOutOfRange { delay, min, max } => {
+                write!(f, "Delay {}ms is out of range {}..={}ms", delay, min, max)
+            }
+            Self::JitterTooLarge { jitter, max } => {
+                write!(f, "Jitter {}ms is greater than {}ms", jitter, max)
+            }
+            Self::InvalidStatus(status) => {
+                write!(f, "Status {} isn't an error status (400-599)", status)
+            }
+        }
+    }
+}
+
+impl ResponseError for FaultError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+// ---- Validation ----
+
+/// Checks the request against the configured bounds and returns
+/// the total delay including jitter and the failure to simulate.
+pub fn plan(
+    delay: u64,
+    query: &FaultQuery,
+    config: &DelayedResponseConfig,
+) -> Result<(Duration, Option<Failure>), FaultError> {
+    check_delay(delay, config)?;
+    if query.jitter > config.max_jitter_ms {
+        return Err(FaultError::JitterTooLarge {
+            jitter: query.jitter,
+            max: config.max_jitter_ms,
+        });
+    }
+    let failure = match query.failure {
+        None => None,
+        Some(FailureKind::Status) => {
+            let status = query.status.unwrap_or(500);
+            match StatusCode::from_u16(status) {
+                Ok(status) if status.is_client_error() || status.is_server_error() => {
+                    Some(Failure::Status(status))
+                }
+                _ => return Err(FaultError::InvalidStatus(status)),
+            }
+        }
+        Some(FailureKind::Partial) => Some(Failure::Partial),
+        Some(FailureKind::Drop) => Some(Failure::Drop),
+    };
+    let jitter = rand::thread_rng().gen_range(0, query.jitter.saturating_add(1));
+    Ok((Duration::from_millis(delay.saturating_add(jitter)), failure))
+}
+
+/// Checks `delay` against `min_delay_ms..=max_delay_ms`.
+pub fn check_delay(delay: u64, config: &DelayedResponseConfig) -> Result<(), FaultError> {
+    if delay < config.min_delay_ms || delay > config.max_delay_ms {
+        return Err(FaultError::DelayOutOfRange {
+            delay,
+            min: config.min_delay_ms,
+            max: config.max_delay_ms,
+        });
+    }
+    Ok(())
+}
+
+// ---- Responses ----
+
+pub fn response(body: String, failure: Option<Failure>) -> HttpResponse {
+    let connection_closed =
+        || error::ErrorInternalServerError("connection closed by fault injection");
+    match failure {
+        None => HttpResponse::Ok().body(body),
+        Some(Failure::Status(status)) => HttpResponse::build(status).body(body),
+        // A body stream error makes actix-web close the connection.
+        Some(Failure::Partial) => {
+            let body = Bytes::from(body);
+            let half = body.slice(..body.len() / 2);
+            HttpResponse::Ok().streaming(stream::iter(vec![Ok(half), Err(connection_closed())]))
+        }
+        Some(Failure::Drop) => {
+            HttpResponse::Ok().streaming(stream::iter(vec![Err::<Bytes, _>(connection_closed())]))
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::count_store::write_atomically;
+use crate::form_data::UploadedFile;
+
+// Hex encoded sha256.
+const ID_LEN: usize = 64;
+
+const METADATA_EXTENSION: &str = "json";
+
+// ---- Store ----
+
+/// Uploads stored under the sha256 of their content, so equal files are stored once.
+/// `<id>` holds the content and `<id>.json` the `shared::StoredFile` metadata.
+/// All methods block, call them through `web::block`.
+#[derive(Debug, Clone)]
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Moves the upload into the store, the metadata of a stored equal file is replaced.
+    pub fn insert(&self, upload: UploadedFile) -> io::Result<shared::StoredFile> {
+        let id = upload.sha256.clone();
+        let path = self.content_path(&id);
+        if !path.is_file() {
+            // Renaming fails across file systems, e.g. from a tmpfs `form.temp_dir`.
+            if let Err(error) = upload.file.persist(&path) {
+                let partial = path.with_extension("partial");
+                fs::copy(error.file.path(), &partial)?;
+                fs::rename(&partial, &path)?;
+            }
+        }
+        let uploaded_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let metadata = shared::StoredFile {
+            id,
+            name: upload.file_name,
+            size: upload.size,
+            content_type: upload.content_type.to_string(),
+            sha256: upload.sha256,
+            uploaded_at,
+        };
+        write_atomically(
+            &self.metadata_path(&metadata.id),
+            &serde_json::to_vec_pretty(&metadata)?,
+        )?;
+        Ok(metadata)
+    }
+
+    /// The metadata and the content path, `None` for unknown or invalid IDs.
+    pub fn get(&self, id: &str) -> io::Result<Option<(shared::StoredFile, PathBuf)>> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+        match fs::read(self.metadata_path(id)) {
+            Ok(content) => {
+                let metadata = serde_json::from_slice(&content)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                Ok(Some((metadata, self.content_path(id))))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// All stored files, the latest uploads first.
+    pub fn list(&self) -> io::Result<Vec<shared::StoredFile>> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(id)
+                    if path
+                        .extension()
+                        .is_some_and(|ext| ext == METADATA_EXTENSION) =>
+                {
+                    id
+                }
+                _ => continue,
+            };
+            if let Some((metadata, _)) = self.get(id)? {
+                files.push(metadata);
+            }
+        }
+        files.sort_by_key(|file| Reverse(file.uploaded_at));
+        Ok(files)
+    }
+
+    /// Returns `false` for unknown IDs.
+    pub fn remove(&self, id: &str) -> io::Result<bool> {
+        if !is_valid_id(id) {
+            return Ok(false);
+        }
+        match fs::remove_file(self.metadata_path(id)) {
+            Ok(()) => (),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err),
+        }
+        match fs::remove_file(self.content_path(id)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(true),
+        }
+    }
+
+    fn content_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    fn metadata_path(&self, id: &str) -> PathBuf {
+        self.dir.join(id).with_extension(METADATA_EXTENSION)
+    }
+}
+
+// IDs are used as file names, so anything else could leave the store.
+fn is_valid_id(id: &str) -> bool {
+    id.len() == ID_LEN && id.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f'))
+}
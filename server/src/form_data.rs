@@ -0,0 +1,242 @@
+use actix_multipart::{Field, Multipart, MultipartError};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, HttpRequest, ResponseError};
+use futures::stream::StreamExt;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fmt;
+use std::io::Write;
+use tempfile::NamedTempFile;
+
+use crate::config::FormConfig;
+use crate::metrics;
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum FormError {
+    PayloadTooLarge(u64),
+    FieldTooLarge { name: String, limit: u64 },
+    UnsupportedContentType { name: String, content_type: String },
+    MissingFieldName,
+    InvalidCheckbox { name: String, value: String },
+    Multipart(String),
+    Storage(String),
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::PayloadTooLarge(limit) => write!(f, "Form has to be at most {} bytes", limit),
+            Self::FieldTooLarge { name, limit } => {
+                write!(f, "Field '{}' has to be at most {} bytes", name, limit)
+            }
+            Self::UnsupportedContentType { name, content_type } => write!(
+                f,
+                "Field '{}' has the unsupported content type '{}'",
+                name, content_type
+            ),
+            Self::MissingFieldName => write!(f, "Form field without a name"),
+            Self::InvalidCheckbox { name, value } => write!(
+                f,
+                "Checkbox '{}' has to be 'true', 'false' or 'on', not '{}'",
+                name, value
+            ),
+            Self::Multipart(reason) => write!(f, "Invalid multipart form: {}", reason),
+            Self::Storage(reason) => write!(f, "Storing the upload failed: {}", reason),
+        }
+    }
+}
+
+impl ResponseError for FormError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::PayloadTooLarge(_) | Self::FieldTooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UnsupportedContentType { .. } => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::MissingFieldName | Self::InvalidCheckbox { .. } | Self::Multipart(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<MultipartError> for FormError {
+    fn from(error: MultipartError) -> Self {
+        Self::Multipart(error.to_string())
+    }
+}
+
+// ---- Fields ----
+
+pub enum FieldValue {
+    Text(String),
+    File(UploadedFile),
+}
+
+/// The value of a text field sent by a checkbox,
+/// browsers send `on` for checked HTML checkboxes and nothing otherwise.
+pub fn parse_checkbox(name: &str, value: &str) -> Result<bool, FormError> {
+    match value {
+        "true" | "on" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(FormError::InvalidCheckbox {
+            name: name.to_owned(),
+            value: value.to_owned(),
+        }),
+    }
+}
+
+/// File field streamed into a temp file, which is deleted on drop.
+pub struct UploadedFile {
+    pub file_name: String,
+    pub content_type: mime::Mime,
+    pub size: u64,
+    /// Hex encoded.
+    pub sha256: String,
+    pub file: NamedTempFile,
+}
+
+/// Reads all fields of the form within the limits of `config`.
+/// Text fields are kept in memory, file fields are streamed into temp files.
+pub async fn read_fields(
+    req: &HttpRequest,
+    mut multipart: Multipart,
+    config: &FormConfig,
+) -> Result<Vec<(String, FieldValue)>, FormError> {
+    // Refused before reading if the client announces the size.
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > config.max_total_bytes) {
+        return Err(FormError::PayloadTooLarge(config.max_total_bytes));
+    }
+
+    let mut received = Received {
+        total: 0,
+        limit: config.max_total_bytes,
+    };
+    let mut fields = Vec::new();
+    while let Some(field) = multipart.next().await {
+        let field = field?;
+        let content_disposition = field.content_disposition();
+        let name = content_disposition
+            .as_ref()
+            .and_then(|content_disposition| content_disposition.get_name())
+            .map(ToOwned::to_owned)
+            .ok_or(FormError::MissingFieldName)?;
+        let file_name = content_disposition
+            .as_ref()
+            .and_then(|content_disposition| content_disposition.get_filename())
+            .map(ToOwned::to_owned);
+
+        let value = match file_name {
+            Some(file_name) => {
+                let file = read_file(field, &name, file_name, &mut received, config).await?;
+                FieldValue::File(file)
+            }
+            None => FieldValue::Text(read_text(field, &name, &mut received, config).await?),
+        };
+        fields.push((name, value));
+    }
+    Ok(fields)
+}
+
+// Bytes received over all fields.
+struct Received {
+    total: u64,
+    limit: u64,
+}
+
+impl Received {
+    fn add(&mut self, bytes: usize) -> Result<(), FormError> {
+        metrics::add_form_received_bytes(bytes);
+        self.total += bytes as u64;
+        if self.total > self.limit {
+            Err(FormError::PayloadTooLarge(self.limit))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+async fn read_text(
+    mut field: Field,
+    name: &str,
+    received: &mut Received,
+    config: &FormConfig,
+) -> Result<String, FormError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk?;
+        received.add(chunk.len())?;
+        if bytes.len() + chunk.len() > config.max_field_bytes {
+            return Err(FormError::FieldTooLarge {
+                name: name.to_owned(),
+                limit: config.max_field_bytes as u64,
+            });
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+async fn read_file(
+    mut field: Field,
+    name: &str,
+    file_name: String,
+    received: &mut Received,
+    config: &FormConfig,
+) -> Result<UploadedFile, FormError> {
+    let content_type = field.content_type().clone();
+    if !is_accepted(&content_type, &config.file_content_types) {
+        return Err(FormError::UnsupportedContentType {
+            name: name.to_owned(),
+            content_type: content_type.to_string(),
+        });
+    }
+
+    // File IO would block the worker.
+    let temp_dir = config.temp_dir.clone().unwrap_or_else(env::temp_dir);
+    let mut file = web::block(move || NamedTempFile::new_in(temp_dir))
+        .await
+        .map_err(|error| FormError::Storage(error.to_string()))?;
+    let mut size = 0;
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk?;
+        received.add(chunk.len())?;
+        size += chunk.len() as u64;
+        if size > config.max_file_bytes {
+            return Err(FormError::FieldTooLarge {
+                name: name.to_owned(),
+                limit: config.max_file_bytes,
+            });
+        }
+        hasher.update(&chunk);
+        file = web::block(move || file.write_all(&chunk).map(|_| file))
+            .await
+            .map_err(|error| FormError::Storage(error.to_string()))?;
+    }
+    Ok(UploadedFile {
+        file_name,
+        content_type,
+        size,
+        sha256: format!("{:x}", hasher.finalize()),
+        file,
+    })
+}
+
+// `text/*` accepts all text types, parameters like `charset` are ignored.
+fn is_accepted(content_type: &mime::Mime, accepted: &[String]) -> bool {
+    accepted
+        .iter()
+        .filter_map(|accepted| accepted.parse::<mime::Mime>().ok())
+        .any(|accepted| {
+            (accepted.type_() == mime::STAR || accepted.type_() == content_type.type_())
+                && (accepted.subtype() == mime::STAR
+                    || accepted.subtype() == content_type.subtype())
+        })
+}
@@ -0,0 +1,217 @@
+use actix::prelude::*;
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::chat_actor::ChatActor;
+use crate::count_actor::CountActor;
+use crate::job_actor::JobActor;
+use crate::user_actor::UserActor;
+use crate::State;
+
+// Files loaded by `index.html` from `pkg_folder`.
+const PKG_FILES: [&str; 2] = ["package.js", "package_bg.wasm"];
+
+const ACTOR_TIMEOUT: Duration = Duration::from_secs(1);
+
+// ---- Messages ----
+
+/// Answered by every actor, so readiness checks can tell it's responding.
+pub struct MsgPing;
+
+impl Message for MsgPing {
+    type Result = ();
+}
+
+impl Handler<MsgPing> for CountActor {
+    type Result = ();
+
+    fn handle(&mut self, _: MsgPing, _: &mut Context<Self>) -> Self::Result {}
+}
+
+impl Handler<MsgPing> for ChatActor {
+    type Result = ();
+
+    fn handle(&mut self, _: MsgPing, _: &mut Context<Self>) -> Self::Result {}
+}
+
+impl Handler<MsgPing> for JobActor {
+    type Result = ();
+
+    fn handle(&mut self, _: MsgPing, _: &mut Context<Self>) -> Self::Result {}
+}
+
+impl Handler<MsgPing> for UserActor {
+    type Result = ();
+
+    fn handle(&mut self, _: MsgPing, _: &mut Context<Self>) -> Self::Result {}
+}
+
+// ---- Endpoints ----
+
+/// The process is alive.
+pub async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().body("ok")
+}
+
+#[derive(Serialize)]
+struct Readiness {
+    ready: bool,
+    checks: BTreeMap<&'static str, String>,
+}
+
+/// The actors respond and the client files can be served.
+pub async fn readyz(state: web::Data<State>) -> HttpResponse {
+    let mut checks = BTreeMap::new();
+    checks.insert(
+        "count_actor",
+        ping(state.count_actor.send(MsgPing).timeout(ACTOR_TIMEOUT)).await,
+    );
+    checks.insert(
+        "chat_actor",
+        ping(state.chat_actor.send(MsgPing).timeout(ACTOR_TIMEOUT)).await,
+    );
+    checks.insert(
+        "job_actor",
+        ping(state.job_actor.send(MsgPing).timeout(ACTOR_TIMEOUT)).await,
+    );
+    checks.insert(
+        "user_actor",
+        ping(state.user_actor.send(MsgPing).timeout(ACTOR_TIMEOUT)).await,
+    );
+    // The embedded client can't be missing.
+    if !cfg!(feature = "embed-client") {
+        checks.insert("index_file", check_file(&state.config.index_file));
+        checks.insert("pkg_folder", check_pkg_folder(&state.config.pkg_folder));
+    }
+
+    let ready = checks.values().all(|check| check == "ok");
+    let readiness = Readiness { ready, checks };
+    if ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+async fn ping(request: impl Future<Output = Result<(), MailboxError>>) -> String {
+    match request.await {
+        Ok(()) => "ok".to_owned(),
+        Err(error) => error.to_string(),
+    }
+}
+
+fn check_file(path: &Path) -> String {
+    if path.is_file() {
+        "ok".to_owned()
+    } else {
+        format!("'{}' not found", path.display())
+    }
+}
+
+fn check_pkg_folder(pkg_folder: &Path) -> String {
+    PKG_FILES
+        .iter()
+        .map(|file| check_file(&pkg_folder.join(file)))
+        .find(|check| check != "ok")
+        .unwrap_or_else(|| "ok".to_owned())
+}
+
+#[derive(Serialize)]
+struct Version {
+    version: &'static str,
+    git_hash: &'static str,
+    build_time: &'static str,
+}
+
+pub async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+        build_time: env!("BUILD_TIME"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use std::fs;
+    use std::sync::Arc;
+
+    use crate::auth::Auth;
+    use crate::chaos::ChaosActor;
+    use crate::config::Config;
+    use crate::count_store::CountStore;
+    use crate::file_store::FileStore;
+    use crate::rate_limiter::RateLimitActor;
+    use crate::sessions::Sessions;
+    use crate::user_store::UserStore;
+
+    fn state(dir: &Path, count_actor: Addr<CountActor>) -> State {
+        fs::write(dir.join("index.html"), "").unwrap();
+        for file in &PKG_FILES {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        let config = Config {
+            index_file: dir.join("index.html"),
+            pkg_folder: dir.to_owned(),
+            ..Config::default()
+        };
+        let sessions = Sessions::new(Duration::from_secs(60), Vec::new(), false);
+        State {
+            count_actor,
+            chat_actor: ChatActor::default().start(),
+            job_actor: JobActor::new(
+                config.jobs.expiry(),
+                config.limits.max_batch_size,
+                config.delayed_response.clone(),
+            )
+            .start(),
+            chaos_actor: ChaosActor::new(config.chaos.clone()).start(),
+            user_actor: UserActor::new(UserStore::Memory, config.users.save_interval())
+                .unwrap()
+                .start(),
+            rate_limit_actor: RateLimitActor::new(config.rate_limit.clone()).start(),
+            file_store: FileStore::new(dir.join("uploads")).unwrap(),
+            auth: Arc::new(Auth::new(&config.auth, sessions)),
+            config,
+        }
+    }
+
+    async fn readyz_status(state: State) -> StatusCode {
+        let mut app = test::init_service(
+            App::new()
+                .data(state)
+                .route("/readyz", web::get().to(readyz)),
+        )
+        .await;
+        let request = test::TestRequest::get().uri("/readyz").to_request();
+        test::call_service(&mut app, request).await.status()
+    }
+
+    fn count_actor() -> CountActor {
+        CountActor::new(CountStore::Memory, Duration::from_secs(60)).unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn ready_while_the_actors_respond() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(dir.path(), count_actor().start());
+        assert_eq!(readyz_status(state).await, StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn unavailable_without_the_count_actor() {
+        let dir = tempfile::tempdir().unwrap();
+        let stopped = CountActor::create(|ctx| {
+            ctx.stop();
+            count_actor()
+        });
+        let state = state(dir.path(), stopped);
+        assert_eq!(readyz_status(state).await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
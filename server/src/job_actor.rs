@@ -0,0 +1,386 @@
+use actix::prelude::*;
+use actix_web::{http::StatusCode, ResponseError};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::config::DelayedResponseConfig;
+use crate::{conversion, fault_injection};
+use shared::{ConversionProgress, JobRequest, JobResult, JobState, JobStatus};
+
+// ---- Actor ----
+
+struct Job {
+    /// `Identity::subject` of the submitter, `None` without credentials.
+    owner: Option<String>,
+    state: JobState,
+    // `None` once the job is finished.
+    handle: Option<SpawnHandle>,
+    finished_at: Option<Instant>,
+}
+
+/// Runs submitted jobs inside its own context, so cancelling a job
+/// drops its future and stops the work.
+/// Jobs are only visible to the client that submitted them.
+pub struct JobActor {
+    jobs: HashMap<u64, Job>,
+    next_id: u64,
+    expiry: Duration,
+    max_batch_size: u32,
+    delay_bounds: DelayedResponseConfig,
+}
+
+impl JobActor {
+    /// Finished and cancelled jobs are forgotten after `expiry`.
+    /// Delays outside of `delay_bounds` are rejected.
+    pub fn new(expiry: Duration, max_batch_size: u32, delay_bounds: DelayedResponseConfig) -> Self {
+        Self {
+            jobs: HashMap::new(),
+            next_id: 0,
+            expiry,
+            max_batch_size,
+            delay_bounds,
+        }
+    }
+
+    fn start_job(&mut self, id: u64, request: JobRequest, ctx: &mut Context<Self>) -> SpawnHandle {
+        match request {
+            JobRequest::Delay { delay_ms } => ctx.spawn(
+                futures_timer::Delay::new(Duration::from_millis(delay_ms))
+                    .into_actor(self)
+                    .map(move |_, actor, _| {
+                        let result = JobResult::Text(format!("Delay was set to {}ms.", delay_ms));
+                        actor.finish(id, JobState::Completed { result });
+                    }),
+            ),
+            JobRequest::BatchConversion { count, delay_ms } => {
+                let step_delay = Duration::from_millis(delay_ms) / count;
+                let progress = conversion::convert_with_progress(
+                    conversion::turntable_batch(count),
+                    step_delay,
+                );
+                ctx.spawn(
+                    Box::pin(progress)
+                        .into_actor(self)
+                        .map(move |progress, actor, _| actor.update(id, progress))
+                        .finish(),
+                )
+            }
+        }
+    }
+
+    fn update(&mut self, id: u64, progress: ConversionProgress) {
+        match progress {
+            ConversionProgress::Started { .. } => (),
+            ConversionProgress::Progress { percent, .. } => {
+                if let Some(job) = self.jobs.get_mut(&id) {
+                    job.state = JobState::Running { percent };
+                }
+            }
+            ConversionProgress::Completed { quaternions } => {
+                let result = JobResult::Quaternions(quaternions);
+                self.finish(id, JobState::Completed { result });
+            }
+        }
+    }
+
+    fn finish(&mut self, id: u64, state: JobState) {
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.state = state;
+            job.handle = None;
+            job.finished_at = Some(Instant::now());
+        }
+    }
+
+    // Jobs of other owners are reported as missing, so their ids don't leak.
+    fn owned_job(&mut self, id: u64, owner: &Option<String>) -> Result<&mut Job, JobError> {
+        self.jobs
+            .get_mut(&id)
+            .filter(|job| job.owner == *owner)
+            .ok_or(JobError::NotFound(id))
+    }
+
+    fn remove_expired(&mut self) {
+        let expiry = self.expiry;
+        self.jobs.retain(|_, job| {
+            job.finished_at
+                .is_none_or(|finished_at| finished_at.elapsed() < expiry)
+        });
+    }
+}
+
+impl Actor for JobActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_interval(self.expiry / 2, |actor, _| actor.remove_expired());
+    }
+}
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum JobError {
+    InvalidRequest(String),
+    NotFound(u64),
+    NoConversionResult(u64),
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidRequest(reason) => write!(f, "Invalid job: {}", reason),
+            Self::NotFound(id) => write!(f, "Job {} not found", id),
+            Self::NoConversionResult(id) => {
+                write!(f, "Job {} hasn't completed a batch conversion", id)
+            }
+        }
+    }
+}
+
+impl ResponseError for JobError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::NoConversionResult(_) => StatusCode::CONFLICT,
+        }
+    }
+}
+
+fn validate(
+    request: &JobRequest,
+    max_batch_size: u32,
+    delay_bounds: &DelayedResponseConfig,
+) -> Result<(), JobError> {
+    let delay_ms = match request {
+        JobRequest::Delay { delay_ms } => *delay_ms,
+        JobRequest::BatchConversion { count, delay_ms } => {
+            if *count == 0 || *count > max_batch_size {
+                return Err(JobError::InvalidRequest(format!(
+                    "batch size has to be between 1 and {}",
+                    max_batch_size
+                )));
+            }
+            *delay_ms
+        }
+    };
+    fault_injection::check_delay(delay_ms, delay_bounds)
+        .map_err(|error| JobError::InvalidRequest(error.to_string()))
+}
+
+// ---- Messages ----
+
+pub struct MsgSubmit {
+    pub request: JobRequest,
+    pub owner: Option<String>,
+}
+
+impl Message for MsgSubmit {
+    type Result = Result<JobStatus, JobError>;
+}
+
+pub struct MsgStatus {
+    pub id: u64,
+    pub owner: Option<String>,
+}
+
+impl Message for MsgStatus {
+    type Result = Result<JobStatus, JobError>;
+}
+
+/// Cancels a running job. Finished jobs keep their state.
+pub struct MsgCancel {
+    pub id: u64,
+    pub owner: Option<String>,
+}
+
+impl Message for MsgCancel {
+    type Result = Result<JobStatus, JobError>;
+}
+
+// ---- Handlers ----
+
+impl Handler<MsgSubmit> for JobActor {
+    type Result = Result<JobStatus, JobError>;
+
+    fn handle(&mut self, msg: MsgSubmit, ctx: &mut Context<Self>) -> Self::Result {
+        validate(&msg.request, self.max_batch_size, &self.delay_bounds)?;
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let state = JobState::Running { percent: 0 };
+        self.jobs.insert(
+            id,
+            Job {
+                owner: msg.owner,
+                state: state.clone(),
+                handle: None,
+                finished_at: None,
+            },
+        );
+        let handle = self.start_job(id, msg.request, ctx);
+        if let Some(job) = self.jobs.get_mut(&id) {
+            job.handle = Some(handle);
+        }
+        Ok(JobStatus { id, state })
+    }
+}
+
+impl Handler<MsgStatus> for JobActor {
+    type Result = Result<JobStatus, JobError>;
+
+    fn handle(&mut self, msg: MsgStatus, _: &mut Context<Self>) -> Self::Result {
+        let job = self.owned_job(msg.id, &msg.owner)?;
+        Ok(JobStatus {
+            id: msg.id,
+            state: job.state.clone(),
+        })
+    }
+}
+
+impl Handler<MsgCancel> for JobActor {
+    type Result = Result<JobStatus, JobError>;
+
+    fn handle(&mut self, msg: MsgCancel, ctx: &mut Context<Self>) -> Self::Result {
+        let job = self.owned_job(msg.id, &msg.owner)?;
+        if let Some(handle) = job.handle.take() {
+            ctx.cancel_future(handle);
+            job.state = JobState::Cancelled;
+            job.finished_at = Some(Instant::now());
+        }
+        Ok(JobStatus {
+            id: msg.id,
+            state: job.state.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDS: DelayedResponseConfig = DelayedResponseConfig {
+        min_delay_ms: 100,
+        max_delay_ms: 1000,
+        max_jitter_ms: 0,
+    };
+
+    fn is_valid(request: JobRequest) -> bool {
+        validate(&request, 10, &BOUNDS).is_ok()
+    }
+
+    #[test]
+    fn delays_within_bounds() {
+        assert!(is_valid(JobRequest::Delay { delay_ms: 100 }));
+        assert!(is_valid(JobRequest::Delay { delay_ms: 1000 }));
+        assert!(!is_valid(JobRequest::Delay { delay_ms: 99 }));
+        assert!(!is_valid(JobRequest::Delay { delay_ms: 1001 }));
+        assert!(!is_valid(JobRequest::Delay { delay_ms: u64::MAX }));
+    }
+
+    #[test]
+    fn batch_conversions_within_bounds() {
+        let batch = |count, delay_ms| JobRequest::BatchConversion { count, delay_ms };
+        assert!(is_valid(batch(10, 500)));
+        assert!(!is_valid(batch(0, 500)));
+        assert!(!is_valid(batch(11, 500)));
+        assert!(!is_valid(batch(10, 50)));
+        assert!(!is_valid(batch(10, 5000)));
+    }
+
+    const OWNER: Option<&str> = Some("user:alice");
+
+    fn start(expiry: Duration) -> Addr<JobActor> {
+        let bounds = DelayedResponseConfig {
+            min_delay_ms: 0,
+            ..BOUNDS
+        };
+        JobActor::new(expiry, 10, bounds).start()
+    }
+
+    async fn submit(actor: &Addr<JobActor>, delay_ms: u64) -> u64 {
+        let msg = MsgSubmit {
+            request: JobRequest::Delay { delay_ms },
+            owner: OWNER.map(ToOwned::to_owned),
+        };
+        actor.send(msg).await.unwrap().unwrap().id
+    }
+
+    async fn status(actor: &Addr<JobActor>, id: u64) -> Result<JobState, JobError> {
+        let msg = MsgStatus {
+            id,
+            owner: OWNER.map(ToOwned::to_owned),
+        };
+        actor.send(msg).await.unwrap().map(|status| status.state)
+    }
+
+    async fn cancel(actor: &Addr<JobActor>, id: u64) -> Result<JobState, JobError> {
+        let msg = MsgCancel {
+            id,
+            owner: OWNER.map(ToOwned::to_owned),
+        };
+        actor.send(msg).await.unwrap().map(|status| status.state)
+    }
+
+    async fn sleep(ms: u64) {
+        futures_timer::Delay::new(Duration::from_millis(ms)).await;
+    }
+
+    #[actix_rt::test]
+    async fn cancelled_jobs_stop_and_stay_cancelled() {
+        let actor = start(Duration::from_secs(60));
+        let id = submit(&actor, 50).await;
+        assert!(matches!(cancel(&actor, id).await, Ok(JobState::Cancelled)));
+
+        // A job that kept running would be completed by now.
+        sleep(150).await;
+        assert!(matches!(status(&actor, id).await, Ok(JobState::Cancelled)));
+    }
+
+    #[actix_rt::test]
+    async fn finished_jobs_keep_their_state_on_cancel() {
+        let actor = start(Duration::from_secs(60));
+        let id = submit(&actor, 0).await;
+        sleep(50).await;
+        assert!(matches!(
+            cancel(&actor, id).await,
+            Ok(JobState::Completed { .. })
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn expired_jobs_are_removed() {
+        let actor = start(Duration::from_millis(50));
+        let finished = submit(&actor, 0).await;
+        let running = submit(&actor, 1000).await;
+        sleep(200).await;
+        assert!(matches!(
+            status(&actor, finished).await,
+            Err(JobError::NotFound(_))
+        ));
+        assert!(matches!(
+            status(&actor, running).await,
+            Ok(JobState::Running { .. })
+        ));
+    }
+
+    #[actix_rt::test]
+    async fn jobs_are_only_visible_to_their_owner() {
+        let actor = start(Duration::from_secs(60));
+        let id = submit(&actor, 1000).await;
+        for owner in [None, Some("user:bob".to_owned())] {
+            let status = actor.send(MsgStatus {
+                id,
+                owner: owner.clone(),
+            });
+            assert!(matches!(status.await.unwrap(), Err(JobError::NotFound(_))));
+            let cancel = actor.send(MsgCancel { id, owner });
+            assert!(matches!(cancel.await.unwrap(), Err(JobError::NotFound(_))));
+        }
+        assert!(matches!(
+            status(&actor, id).await,
+            Ok(JobState::Running { .. })
+        ));
+    }
+}
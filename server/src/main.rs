@@ -1,138 +1,795 @@
 use actix::prelude::*;
-use actix_files::{Files, NamedFile};
+use actix_files::NamedFile;
 use actix_multipart::Multipart;
-use actix_web::{get, post, web, App, HttpServer, Result};
+use actix_web::dev::{BodyEncoding, Service};
+use actix_web::http::header::{
+    self, Charset, ContentDisposition, DispositionParam, DispositionType, ExtendedValue,
+};
+use actix_web::middleware::{Compress, Condition};
+use actix_web::{
+    delete, error, get, post, web, App, HttpRequest, HttpResponse, HttpServer, Result,
+};
+use actix_web_actors::ws;
+use futures::future::{self, Either};
 use futures::stream::StreamExt;
-use nalgebra::{Matrix3, Rotation3, UnitQuaternion};
-use std::env;
+use serde::Deserialize;
 use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::process;
+use std::sync::Arc;
 use std::time;
+use structopt::StructOpt;
+use tracing::{debug, info, info_span, instrument};
+use tracing_futures::Instrument;
+use tracing_subscriber::EnvFilter;
 
+mod accounts;
+mod assets;
+mod auth;
+use auth::{Auth, Authenticate, Identity};
+mod chaos;
+use chaos::{Chaos, ChaosActor};
+mod chat_actor;
+use chat_actor::{ChatActor, MsgBroadcast};
+mod chat_session;
+use chat_session::ChatSession;
 mod count_actor;
-use count_actor::{CountActor, MsgIncrement};
+use count_actor::{
+    CountActor, CountError, MsgDelete, MsgFlush, MsgGet, MsgIncrement, MsgList, MsgReset,
+    MESSAGES_COUNTER,
+};
+mod config;
+use config::{Cli, Config, FormConfig, LogFormat, LoggingConfig};
+mod conversion;
+mod cors;
+use cors::Cors;
+mod count_store;
+mod fault_injection;
+use fault_injection::{FaultError, FaultQuery};
+mod file_store;
+use file_store::FileStore;
+mod form_data;
+use form_data::{FieldValue, FormError};
+mod health;
+mod job_actor;
+mod matrix_file;
+use matrix_file::{CsvOptions, ExportError, ImportError, MatrixFileFormat, ResultKind};
+mod metrics;
+use metrics::RequestMetrics;
+mod rate_limiter;
+use rate_limiter::{MsgAcquire, RateLimitActor, RateLimiter};
+mod request_logger;
+mod sessions;
+use sessions::Sessions;
+mod user_actor;
+use user_actor::{MsgHistory, MsgRecordMessage, UserActor};
+mod user_store;
+use request_logger::RequestLogger;
+mod tls;
+use job_actor::{JobActor, JobError, MsgCancel, MsgStatus, MsgSubmit};
 
 // ---- Apis ("/api/*") ----
 
 #[post("send-message")]
+#[instrument(skip(state, identity, request_data))]
 async fn send_message(
     state: web::Data<State>,
+    identity: Option<Identity>,
     request_data: web::Json<shared::SendMessageRequestBody>,
 ) -> Result<web::Json<shared::SendMessageResponseBody>> {
-    Ok(web::Json(shared::SendMessageResponseBody {
-        ordinal_number: state
+    let response_data = shared::SendMessageResponseBody {
+        ordinal_number: metrics::count_actor_mailbox(
+            state
+                .count_actor
+                .send(MsgIncrement::new(MESSAGES_COUNTER, 1)),
+        )
+        .instrument(info_span!("count_actor_send", counter = MESSAGES_COUNTER))
+        .await
+        .expect("send MsgIncrement")?,
+        text: request_data.into_inner().text,
+    };
+    if let Some(username) = identity.as_ref().and_then(Identity::username) {
+        state.user_actor.do_send(MsgRecordMessage {
+            username: username.to_owned(),
+            message: response_data.clone(),
+        });
+    }
+    state
+        .chat_actor
+        .do_send(MsgBroadcast(response_data.clone()));
+    Ok(web::Json(response_data))
+}
+
+/// Messages sent by the logged-in user, oldest first, none for API keys.
+#[get("messages")]
+async fn message_history(
+    state: web::Data<State>,
+    identity: Identity,
+) -> web::Json<Vec<shared::SendMessageResponseBody>> {
+    let username = match identity.username() {
+        Some(username) => username.to_owned(),
+        None => return web::Json(Vec::new()),
+    };
+    let messages = state
+        .user_actor
+        .send(MsgHistory { username })
+        .await
+        .expect("send MsgHistory");
+    web::Json(messages)
+}
+
+#[get("chat")]
+async fn chat(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    request: HttpRequest,
+    stream: web::Payload,
+) -> Result<HttpResponse> {
+    let rate_limit_config = &state.config.rate_limit;
+    // Every message takes a token like a request to `/api/send-message`.
+    let rate_limit = if rate_limit_config.enabled {
+        let ip = rate_limiter::client_ip(
+            request.peer_addr(),
+            request.headers(),
+            &rate_limit_config.trusted_proxies,
+        );
+        let msg = MsgAcquire::new(identity.as_ref(), ip, "/api/send-message");
+        Some((state.rate_limit_actor.clone(), msg))
+    } else {
+        None
+    };
+    ws::start(
+        ChatSession::new(
+            state.chat_actor.clone(),
+            state.count_actor.clone(),
+            rate_limit,
+        ),
+        &request,
+        stream,
+    )
+}
+
+#[get("counters")]
+async fn list_counters(state: web::Data<State>) -> web::Json<Vec<shared::Counter>> {
+    let counts = metrics::count_actor_mailbox(state.count_actor.send(MsgList))
+        .await
+        .expect("send MsgList");
+    web::Json(
+        counts
+            .into_iter()
+            .map(|(name, value)| shared::Counter { name, value })
+            .collect(),
+    )
+}
+
+#[get("counters/{name}")]
+async fn get_counter(
+    state: web::Data<State>,
+    name: web::Path<String>,
+) -> Result<web::Json<shared::Counter>, CountError> {
+    let name = name.into_inner();
+    let value = metrics::count_actor_mailbox(state.count_actor.send(MsgGet { name: name.clone() }))
+        .await
+        .expect("send MsgGet")?;
+    Ok(web::Json(shared::Counter { name, value }))
+}
+
+// Without a body the step is 1, an invalid body is rejected.
+#[post("counters/{name}/increment")]
+#[instrument(skip(state, body))]
+async fn increment_counter(
+    state: web::Data<State>,
+    name: web::Path<String>,
+    body: web::Bytes,
+) -> Result<web::Json<shared::Counter>> {
+    let name = name.into_inner();
+    let request_data: shared::IncrementCounterRequestBody = if body.is_empty() {
+        Default::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|error| {
+            error::ErrorBadRequest(format!("Invalid increment request: {}", error))
+        })?
+    };
+    let step = request_data.step;
+    let value = metrics::count_actor_mailbox(
+        state
             .count_actor
-            .send(MsgIncrement)
+            .send(MsgIncrement::new(name.as_str(), step)),
+    )
+    .instrument(info_span!("count_actor_send", counter = %name))
+    .await
+    .expect("send MsgIncrement")?;
+    Ok(web::Json(shared::Counter { name, value }))
+}
+
+#[post("counters/{name}/reset")]
+async fn reset_counter(
+    state: web::Data<State>,
+    name: web::Path<String>,
+) -> Result<web::Json<shared::Counter>, CountError> {
+    let name = name.into_inner();
+    let value =
+        metrics::count_actor_mailbox(state.count_actor.send(MsgReset { name: name.clone() }))
             .await
-            .expect("send MsgIncrement"),
-        text: request_data.text.clone(),
+            .expect("send MsgReset")?;
+    Ok(web::Json(shared::Counter { name, value }))
+}
+
+#[delete("counters/{name}")]
+async fn delete_counter(
+    state: web::Data<State>,
+    name: web::Path<String>,
+) -> Result<HttpResponse, CountError> {
+    metrics::count_actor_mailbox(state.count_actor.send(MsgDelete {
+        name: name.into_inner(),
     }))
+    .await
+    .expect("send MsgDelete")?;
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[get("delayed-response/{delay}")]
-async fn delayed_response(delay: web::Path<u64>) -> String {
-    futures_timer::Delay::new(time::Duration::from_millis(*delay)).await;
-    format!("Delay was set to {}ms.", delay)
+async fn delayed_response(
+    state: web::Data<State>,
+    delay: web::Path<u64>,
+    query: web::Query<FaultQuery>,
+) -> Result<HttpResponse, FaultError> {
+    let (total_delay, failure) =
+        fault_injection::plan(*delay, &query, &state.config.delayed_response)?;
+    futures_timer::Delay::new(total_delay).await;
+    Ok(fault_injection::response(
+        format!("Delay was set to {}ms.", delay),
+        failure,
+    ))
+}
+
+#[post("jobs")]
+async fn submit_job(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    request_data: web::Json<shared::JobRequest>,
+) -> Result<HttpResponse, JobError> {
+    let status = state
+        .job_actor
+        .send(MsgSubmit {
+            request: request_data.into_inner(),
+            owner: identity.map(|identity| identity.subject),
+        })
+        .await
+        .expect("send MsgSubmit")?;
+    Ok(HttpResponse::Accepted().json(status))
+}
+
+#[get("jobs/{id}")]
+async fn job_status(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    id: web::Path<u64>,
+) -> Result<web::Json<shared::JobStatus>, JobError> {
+    let status = state
+        .job_actor
+        .send(MsgStatus {
+            id: *id,
+            owner: identity.map(|identity| identity.subject),
+        })
+        .await
+        .expect("send MsgStatus")?;
+    Ok(web::Json(status))
+}
+
+#[delete("jobs/{id}")]
+async fn cancel_job(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    id: web::Path<u64>,
+) -> Result<web::Json<shared::JobStatus>, JobError> {
+    let status = state
+        .job_actor
+        .send(MsgCancel {
+            id: *id,
+            owner: identity.map(|identity| identity.subject),
+        })
+        .await
+        .expect("send MsgCancel")?;
+    Ok(web::Json(status))
+}
+
+#[get("jobs/{id}/export")]
+async fn export_job(
+    state: web::Data<State>,
+    identity: Option<Identity>,
+    id: web::Path<u64>,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let status = state
+        .job_actor
+        .send(MsgStatus {
+            id: *id,
+            owner: identity.map(|identity| identity.subject),
+        })
+        .await
+        .expect("send MsgStatus")?;
+    match status.state {
+        shared::JobState::Completed {
+            result: shared::JobResult::Quaternions(quaternions),
+        } => export_response(&quaternions, &query).map_err(Into::into),
+        _ => Err(JobError::NoConversionResult(*id).into()),
+    }
+}
+
+#[derive(Deserialize)]
+struct FormQuery {
+    /// Comma separated names of the fields sent by checkboxes.
+    checkboxes: Option<String>,
 }
 
 #[post("form")]
-async fn form(mut form: Multipart) -> String {
-    let mut name_text_pairs: Vec<(String, String)> = Vec::new();
-    while let Some(Ok(mut field)) = form.next().await {
-        let field_name = field
-            .content_disposition()
-            .and_then(|cd| cd.get_name().map(ToString::to_string))
-            .expect("Can't get field name!");
-
-        let mut field_bytes: Vec<u8> = Vec::new();
-        while let Some(Ok(bytes)) = field.next().await {
-            for byte in bytes {
-                field_bytes.push(byte)
+async fn form(
+    req: HttpRequest,
+    multipart: Multipart,
+    query: web::Query<FormQuery>,
+    state: web::Data<State>,
+) -> Result<HttpResponse, FormError> {
+    let checkboxes: Vec<_> = query
+        .checkboxes
+        .as_deref()
+        .map_or_else(Vec::new, |names| names.split(',').collect());
+    let fields = form_data::read_fields(&req, multipart, &state.config.form).await?;
+
+    let mut submission = shared::FormSubmission::default();
+    for (name, value) in fields {
+        let value = match value {
+            FieldValue::Text(text) if checkboxes.contains(&name.as_str()) => {
+                shared::FormValue::Bool(form_data::parse_checkbox(&name, &text)?)
             }
-        }
+            FieldValue::Text(text) => shared::FormValue::Text(text),
+            FieldValue::File(file) => {
+                let file_store = state.file_store.clone();
+                let stored = web::block(move || file_store.insert(file))
+                    .await
+                    .map_err(|error| FormError::Storage(error.to_string()))?;
+                shared::FormValue::File(stored)
+            }
+        };
+        submission.fields.push(shared::FormField { name, value });
+    }
 
-        let field_text = String::from_utf8_lossy(&field_bytes).into_owned();
-        name_text_pairs.push((field_name, field_text));
+    let mut response = HttpResponse::Ok();
+    response.header(header::VARY, "Accept");
+    if prefers_text(&req) {
+        Ok(response
+            .content_type("text/plain; charset=utf-8")
+            .body(submission_text(&submission)))
+    } else {
+        Ok(response.json(submission))
     }
+}
+
+// `text/plain` only when it's the first media type in `Accept`, otherwise JSON.
+fn prefers_text(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|accept| accept.split(',').next())
+        .is_some_and(|media_type| {
+            media_type.split(';').next().unwrap_or_default().trim() == "text/plain"
+        })
+}
 
+// The format of the form echo before it was JSON.
+fn submission_text(submission: &shared::FormSubmission) -> String {
     let mut output = String::new();
-    for (name, text) in name_text_pairs {
-        writeln!(&mut output, "{}: {}", name, text).unwrap();
+    for field in &submission.fields {
+        match &field.value {
+            shared::FormValue::Text(text) => {
+                writeln!(&mut output, "{}: {}", field.name, text).unwrap()
+            }
+            shared::FormValue::Bool(boolean) => {
+                writeln!(&mut output, "{}: {}", field.name, boolean).unwrap()
+            }
+            shared::FormValue::File(file) => writeln!(
+                &mut output,
+                "{}: {} ({}, {} bytes) /api/files/{}",
+                field.name, file.name, file.content_type, file.size, file.id
+            )
+            .unwrap(),
+        }
         writeln!(&mut output, "___________________").unwrap();
     }
     output
 }
 
+#[get("files")]
+async fn list_files(state: web::Data<State>) -> Result<web::Json<Vec<shared::StoredFile>>> {
+    let file_store = state.file_store.clone();
+    Ok(web::Json(web::block(move || file_store.list()).await?))
+}
+
+#[get("files/{id}")]
+async fn download_file(
+    req: HttpRequest,
+    state: web::Data<State>,
+    id: web::Path<String>,
+) -> Result<HttpResponse> {
+    let file_store = state.file_store.clone();
+    let (metadata, path) = web::block(move || file_store.get(&id))
+        .await?
+        .ok_or_else(|| error::ErrorNotFound("File not found"))?;
+
+    let content_type = metadata
+        .content_type
+        .parse()
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+    let file_name = if metadata.name.is_ascii() {
+        DispositionParam::Filename(metadata.name)
+    } else {
+        DispositionParam::FilenameExt(ExtendedValue {
+            charset: Charset::Ext("UTF-8".to_owned()),
+            language_tag: None,
+            value: metadata.name.into_bytes(),
+        })
+    };
+    // Downloaded instead of rendered, uploads aren't trusted.
+    let mut response = NamedFile::open(path)?
+        .set_content_type(content_type)
+        .set_content_disposition(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![file_name],
+        })
+        .into_response(&req)?;
+    response.headers_mut().insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        header::HeaderValue::from_static("nosniff"),
+    );
+    Ok(response)
+}
+
+#[delete("files/{id}")]
+async fn delete_file(state: web::Data<State>, id: web::Path<String>) -> Result<HttpResponse> {
+    let file_store = state.file_store.clone();
+    if web::block(move || file_store.remove(&id)).await? {
+        Ok(HttpResponse::NoContent().finish())
+    } else {
+        Err(error::ErrorNotFound("File not found"))
+    }
+}
+
 #[post("matrix")]
+#[instrument(skip(request_data))]
 async fn matrix(
-    request_data: web::Json<shared::RotationMatrix>,
+    request_data: Result<web::Json<shared::RotationMatrix>>,
 ) -> Result<web::Json<shared::Quaternion>> {
-    let flat_mat = &request_data.values;
-    let m = Matrix3::new(
-        flat_mat[0],
-        flat_mat[1],
-        flat_mat[2],
-        flat_mat[3],
-        flat_mat[4],
-        flat_mat[5],
-        flat_mat[6],
-        flat_mat[7],
-        flat_mat[8],
-    );
-    let rot_matrix = Rotation3::from_matrix(&m);
-    let q = UnitQuaternion::from_rotation_matrix(&rot_matrix);
-    Ok(web::Json(shared::Quaternion {
-        x: q[0],
-        y: q[1],
-        z: q[2],
-        w: q[3],
-    }))
+    let request_data = request_data.inspect_err(|_| {
+        metrics::inc_matrix_conversion_errors("invalid_json");
+    })?;
+    if request_data.values.iter().any(|value| !value.is_finite()) {
+        metrics::inc_matrix_conversion_errors("non_finite");
+        return Err(error::ErrorBadRequest(
+            "Matrix values have to be finite numbers.",
+        ));
+    }
+    let quaternion = conversion::to_quaternion(&request_data);
+    debug!(matrix = ?request_data.values, ?quaternion, "matrix converted");
+    Ok(web::Json(quaternion))
 }
 
-async fn index() -> Result<NamedFile> {
-    Ok(NamedFile::open(get_index_file())?)
+#[derive(Deserialize)]
+struct MatrixImportQuery {
+    /// The format of the uploaded file by default.
+    format: Option<MatrixFileFormat>,
 }
 
-struct State {
-    count_actor: Addr<CountActor>,
+// Converts all matrices of the uploaded `file` field.
+#[post("matrix/import")]
+async fn import_matrices(
+    req: HttpRequest,
+    multipart: Multipart,
+    query: web::Query<MatrixImportQuery>,
+    state: web::Data<State>,
+) -> Result<HttpResponse, ImportError> {
+    // The format is checked by parsing, browsers send `.npy` files without a specific type.
+    let form_config = FormConfig {
+        file_content_types: vec!["*/*".to_owned()],
+        ..state.config.form.clone()
+    };
+    let file = form_data::read_fields(&req, multipart, &form_config)
+        .await?
+        .into_iter()
+        .find_map(|(name, value)| match value {
+            FieldValue::File(file) if name == "file" => Some(file),
+            _ => None,
+        })
+        .ok_or(ImportError::MissingFile)?;
+
+    let input_format = MatrixFileFormat::detect(&file.file_name, &file.content_type)?;
+    let content = web::block(move || fs::read(file.file.path()))
+        .await
+        .map_err(|error| FormError::Storage(error.to_string()))?;
+    let matrices = matrix_file::read_matrices(input_format, &content)?;
+    let quaternions: Vec<_> = matrices.iter().map(conversion::to_quaternion).collect();
+    debug!(count = quaternions.len(), "matrices imported");
+
+    let output_format = query.format.unwrap_or(input_format);
+    Ok(HttpResponse::Ok()
+        .content_type(output_format.content_type())
+        .body(matrix_file::write_results(
+            output_format,
+            ResultKind::Quaternions,
+            &quaternions,
+            &CsvOptions::all(ResultKind::Quaternions),
+        )))
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: MatrixFileFormat,
+    #[serde(default)]
+    data: ResultKind,
+    /// Comma separated, CSV only.
+    columns: Option<String>,
+    /// Decimal places, CSV only.
+    precision: Option<usize>,
 }
 
-fn get_server_port() -> u16 {
-    env::var("PORT")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or(3333)
+// A download of the results, converted from the quaternions.
+fn export_response(
+    quaternions: &[shared::Quaternion],
+    query: &ExportQuery,
+) -> Result<HttpResponse, ExportError> {
+    let csv_options = CsvOptions::new(query.data, query.columns.as_deref(), query.precision)?;
+    let file_name = format!("{}.{}", query.data.file_stem(), query.format.extension());
+    Ok(HttpResponse::Ok()
+        .content_type(query.format.content_type())
+        .set(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(file_name)],
+        })
+        .body(matrix_file::write_results(
+            query.format,
+            query.data,
+            quaternions,
+            &csv_options,
+        )))
 }
 
-fn get_pkg_folder() -> String {
-    env::var("PKG_FOLDER")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or("./client/pkg".into())
+// The quaternions of a completed `GET batch-conversion/{delay}`, sent back by the client.
+// Read as bytes, a full batch is larger than `limits.json_payload_bytes`.
+#[post("batch-conversion/export")]
+async fn export_batch_conversion(
+    state: web::Data<State>,
+    body: web::Bytes,
+    query: web::Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let quaternions: Vec<shared::Quaternion> = serde_json::from_slice(&body)
+        .map_err(|error| error::ErrorBadRequest(format!("Invalid quaternions: {}", error)))?;
+    let max_batch_size = state.config.limits.max_batch_size as usize;
+    if quaternions.is_empty() || quaternions.len() > max_batch_size {
+        return Err(error::ErrorBadRequest(format!(
+            "Batch size has to be between 1 and {}.",
+            max_batch_size
+        )));
+    }
+    export_response(&quaternions, &query).map_err(Into::into)
 }
 
-fn get_index_file() -> String {
-    env::var("INDEX")
-        .ok()
-        .and_then(|p| p.parse().ok())
-        .unwrap_or("./client/index.html".into())
+#[derive(Deserialize)]
+struct BatchConversionQuery {
+    #[serde(default = "default_batch_size")]
+    count: u32,
+}
+
+const fn default_batch_size() -> u32 {
+    36
+}
+
+// Streams `shared::ConversionProgress` events, the whole batch takes `delay` ms.
+#[get("batch-conversion/{delay}")]
+async fn batch_conversion(
+    state: web::Data<State>,
+    delay: web::Path<u64>,
+    query: web::Query<BatchConversionQuery>,
+) -> Result<HttpResponse> {
+    let count = query.count;
+    let max_batch_size = state.config.limits.max_batch_size;
+    if count == 0 || count > max_batch_size {
+        return Err(error::ErrorBadRequest(format!(
+            "Batch size has to be between 1 and {}.",
+            max_batch_size
+        )));
+    }
+    fault_injection::check_delay(*delay, &state.config.delayed_response)?;
+    let step_delay = time::Duration::from_millis(*delay) / count;
+    let events = conversion::convert_with_progress(conversion::turntable_batch(count), step_delay)
+        .map(|event| conversion::sse_event(&event).map_err(error::ErrorInternalServerError));
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        // Compressed events would be buffered.
+        .encoding(header::ContentEncoding::Identity)
+        .streaming(Box::pin(events)))
+}
+
+struct State {
+    config: Config,
+    count_actor: Addr<CountActor>,
+    chat_actor: Addr<ChatActor>,
+    job_actor: Addr<JobActor>,
+    chaos_actor: Addr<ChaosActor>,
+    user_actor: Addr<UserActor>,
+    rate_limit_actor: Addr<RateLimitActor>,
+    file_store: FileStore,
+    auth: Arc<Auth>,
+}
+
+fn init_logging(config: &LoggingConfig) {
+    let subscriber = tracing_subscriber::fmt().with_env_filter(EnvFilter::new(&config.level));
+    match config.format {
+        LogFormat::Pretty => subscriber.pretty().init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
 }
 
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    HttpServer::new(move || {
+    let cli = Cli::from_args();
+    let config = Config::load(&cli).unwrap_or_else(|error| {
+        eprintln!("Invalid configuration: {}", error);
+        process::exit(2)
+    });
+    if cli.print_config {
+        let toml = config.to_toml().expect("serialize config");
+        print!("{}", toml);
+        return Ok(());
+    }
+    init_logging(&config.logging);
+
+    let count_actor = CountActor::new(
+        config.count_store.store(),
+        config.count_store.snapshot_interval(),
+    )?
+    .start();
+    let chat_actor = ChatActor::default().start();
+    let job_actor = JobActor::new(
+        config.jobs.expiry(),
+        config.limits.max_batch_size,
+        config.delayed_response.clone(),
+    )
+    .start();
+    let rate_limit_actor = RateLimitActor::new(config.rate_limit.clone()).start();
+    let chaos_actor = ChaosActor::new(config.chaos.clone()).start();
+    let user_actor = UserActor::new(config.users.store(), config.users.save_interval())?.start();
+    let file_store = FileStore::new(config.uploads.dir.clone())?;
+    let sessions = Sessions::new(
+        config.users.session_ttl(),
+        config.users.scopes.clone(),
+        config.tls.is_some(),
+    );
+    let auth = Arc::new(Auth::new(&config.auth, sessions));
+
+    let tls_resolver = match &config.tls {
+        Some(tls_config) => Some(Arc::new(tls::CertResolver::load(tls_config)?)),
+        None => None,
+    };
+    // Plain HTTP requests are redirected once HTTPS is enabled.
+    let https_port = tls_resolver.as_ref().map(|_| config.port);
+
+    let app_config = config.clone();
+    // The server closure takes `count_actor` and `user_actor`.
+    let flush_actor = count_actor.clone();
+    let flush_user_actor = user_actor.clone();
+    let server = HttpServer::new(move || {
         App::new()
+            .wrap_fn(move |req, srv| match https_port {
+                Some(https_port) if !req.app_config().secure() => {
+                    Either::Left(future::ok(tls::https_redirect(req, https_port)))
+                }
+                _ => Either::Right(srv.call(req)),
+            })
+            .wrap(Compress::default())
+            .wrap(Chaos::new(chaos_actor.clone()))
+            .wrap(Condition::new(
+                app_config.rate_limit.enabled,
+                RateLimiter::new(
+                    rate_limit_actor.clone(),
+                    auth.clone(),
+                    app_config.rate_limit.trusted_proxies.clone(),
+                ),
+            ))
+            // Outside of the rate limiter, so that its errors are readable cross-origin.
+            .wrap(Cors::new(app_config.cors.clone()))
+            .wrap(RequestMetrics)
+            .wrap(RequestLogger)
+            .data(State {
+                config: app_config.clone(),
+                count_actor: count_actor.clone(),
+                chat_actor: chat_actor.clone(),
+                job_actor: job_actor.clone(),
+                chaos_actor: chaos_actor.clone(),
+                user_actor: user_actor.clone(),
+                rate_limit_actor: rate_limit_actor.clone(),
+                file_store: file_store.clone(),
+                auth: auth.clone(),
+            })
+            .app_data(web::JsonConfig::default().limit(app_config.limits.json_payload_bytes))
             .service(
                 web::scope("/api/")
+                    .wrap(Authenticate::new(auth.clone(), app_config.auth.enabled))
                     .service(send_message)
+                    .service(message_history)
+                    .service(chat)
+                    .service(list_counters)
+                    .service(get_counter)
+                    .service(increment_counter)
+                    .service(reset_counter)
+                    .service(delete_counter)
                     .service(delayed_response)
                     .service(form)
+                    .service(list_files)
+                    .service(download_file)
+                    .service(delete_file)
                     .service(matrix)
-                    .default_service(web::route().to(web::HttpResponse::NotFound)),
+                    .service(import_matrices)
+                    .service(export_batch_conversion)
+                    .service(batch_conversion)
+                    .service(submit_job)
+                    .service(job_status)
+                    .service(cancel_job)
+                    .service(export_job)
+                    .default_service(web::route().to(HttpResponse::NotFound)),
             )
-            .service(Files::new("/pkg", get_pkg_folder()))
-            .default_service(web::get().to(index))
-    })
-    .bind(format!("0.0.0.0:{}", get_server_port()))?
-    .run()
-    .await
+            .route("/auth/token", web::post().to(auth::token))
+            .route("/auth/register", web::post().to(accounts::register))
+            .route("/auth/login", web::post().to(accounts::login))
+            .route("/auth/logout", web::post().to(accounts::logout))
+            .route("/auth/session", web::get().to(accounts::session))
+            .route("/metrics", web::get().to(metrics::metrics))
+            .route("/healthz", web::get().to(health::healthz))
+            .route("/readyz", web::get().to(health::readyz))
+            .route("/version", web::get().to(health::version))
+            .configure(|cfg| {
+                if app_config.chaos.admin_endpoint {
+                    cfg.service(
+                        web::resource("/admin/chaos")
+                            .wrap(Authenticate::new(auth.clone(), app_config.auth.enabled))
+                            .route(web::get().to(chaos::settings))
+                            .route(web::put().to(chaos::update)),
+                    );
+                }
+            })
+            .route("/pkg/{path:.*}", web::get().to(assets::pkg))
+            .default_service(web::route().to(assets::client_route))
+    });
+
+    let bind_address = (config.bind_address, config.port);
+    let server = match (tls_resolver, &config.tls) {
+        (Some(tls_resolver), Some(tls_config)) => {
+            tls::reload_on_sighup(tls_resolver.clone());
+            let server = server.bind_rustls(bind_address, tls::server_config(tls_resolver))?;
+            match tls_config.redirect_port {
+                Some(redirect_port) => server.bind((config.bind_address, redirect_port))?,
+                None => server,
+            }
+        }
+        _ => server.bind(bind_address)?,
+    };
+
+    // On SIGTERM / SIGINT the server stops accepting connections
+    // and waits up to `shutdown_timeout_secs` for in-flight requests.
+    server
+        .shutdown_timeout(config.shutdown_timeout_secs)
+        .run()
+        .await?;
+
+    info!("server stopped, flushing counters and messages");
+    let messages_flushed = flush_user_actor
+        .send(user_actor::MsgFlush)
+        .await
+        .map_err(|error| io::Error::other(error.to_string()))?;
+    let counters_flushed = flush_actor
+        .send(MsgFlush)
+        .await
+        .map_err(|error| io::Error::other(error.to_string()))?;
+    messages_flushed.and(counters_flushed)
 }
@@ -0,0 +1,679 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpResponse, ResponseError};
+use serde::Deserialize;
+use std::convert::TryInto;
+use std::fmt::{self, Write};
+use std::path::Path;
+
+use shared::{Quaternion, RotationMatrix, RowError};
+
+use crate::conversion;
+use crate::form_data::FormError;
+
+const NPY_MAGIC: &[u8] = b"\x93NUMPY";
+
+// More aren't useful to fix a file by hand.
+const MAX_ROW_ERRORS: usize = 100;
+
+// Digits beyond are noise for `f64`.
+const MAX_PRECISION: usize = 17;
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum ImportError {
+    MissingFile,
+    UnknownFormat(String),
+    InvalidFile(String),
+    InvalidRows(Vec<RowError>),
+    Form(FormError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingFile => write!(f, "Missing form field 'file'"),
+            Self::UnknownFormat(file_name) => write!(
+                f,
+                "Can't tell the format of '{}', use a .csv, .json or .npy file",
+                file_name
+            ),
+            Self::InvalidFile(reason) => write!(f, "Invalid matrix file: {}", reason),
+            Self::InvalidRows(errors) => write!(f, "{} invalid rows", errors.len()),
+            Self::Form(error) => error.fmt(f),
+        }
+    }
+}
+
+impl ResponseError for ImportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::MissingFile | Self::InvalidFile(_) => StatusCode::BAD_REQUEST,
+            Self::UnknownFormat(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::InvalidRows(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::Form(error) => error.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::InvalidRows(errors) => HttpResponse::build(self.status_code()).json(
+                shared::MatrixImportErrorResponseBody {
+                    errors: errors.iter().take(MAX_ROW_ERRORS).cloned().collect(),
+                    invalid_rows: errors.len(),
+                },
+            ),
+            _ => HttpResponse::build(self.status_code()).body(self.to_string()),
+        }
+    }
+}
+
+impl From<FormError> for ImportError {
+    fn from(error: FormError) -> Self {
+        Self::Form(error)
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    UnknownColumn(String),
+    InvalidPrecision(usize),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownColumn(name) => write!(f, "Unknown column '{}'", name),
+            Self::InvalidPrecision(precision) => write!(
+                f,
+                "Precision has to be at most {}, found {}",
+                MAX_PRECISION, precision
+            ),
+        }
+    }
+}
+
+impl ResponseError for ExportError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+// ---- Formats ----
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatrixFileFormat {
+    Csv,
+    Json,
+    Npy,
+}
+
+impl MatrixFileFormat {
+    /// By the file extension, or the content type for files without a known one.
+    pub fn detect(file_name: &str, content_type: &mime::Mime) -> Result<Self, ImportError> {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match (extension.as_str(), content_type.subtype().as_str()) {
+            ("csv", _) | (_, "csv") => Ok(Self::Csv),
+            ("json", _) | (_, "json") => Ok(Self::Json),
+            ("npy", _) => Ok(Self::Npy),
+            _ => Err(ImportError::UnknownFormat(file_name.to_owned())),
+        }
+    }
+
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Json => "json",
+            Self::Npy => "npy",
+        }
+    }
+
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Json => "application/json",
+            Self::Npy => "application/octet-stream",
+        }
+    }
+}
+
+// ---- Import ----
+
+/// Parses all matrices of the file, any invalid row rejects the whole file.
+/// CSV rows have the 9 values of a row-major matrix, a header row is skipped.
+/// JSON files are arrays of 9 numbers, 3 rows of 3 numbers or `{"values": [...]}` objects.
+/// `.npy` files are float64 arrays of shape `(n, 3, 3)` or `(n, 9)`.
+pub fn read_matrices(
+    format: MatrixFileFormat,
+    content: &[u8],
+) -> Result<Vec<RotationMatrix>, ImportError> {
+    let rows = match format {
+        MatrixFileFormat::Csv => csv_rows(content)?,
+        MatrixFileFormat::Json => json_rows(content)?,
+        MatrixFileFormat::Npy => npy_rows(content)?,
+    };
+    if rows.is_empty() {
+        return Err(ImportError::InvalidFile("no matrices".to_owned()));
+    }
+
+    let mut matrices = Vec::with_capacity(rows.len());
+    let mut errors = Vec::new();
+    for (row, values) in rows {
+        match values.and_then(to_matrix) {
+            Ok(matrix) => matrices.push(matrix),
+            Err(message) => errors.push(RowError { row, message }),
+        }
+    }
+    if errors.is_empty() {
+        Ok(matrices)
+    } else {
+        Err(ImportError::InvalidRows(errors))
+    }
+}
+
+// Row numbers with the values, or why the row couldn't be read.
+type Rows = Vec<(usize, Result<Vec<f64>, String>)>;
+
+fn to_matrix(values: Vec<f64>) -> Result<RotationMatrix, String> {
+    let values: [f64; 9] = values
+        .as_slice()
+        .try_into()
+        .map_err(|_| format!("expected 9 values, found {}", values.len()))?;
+    if values.iter().any(|value| !value.is_finite()) {
+        return Err("values have to be finite numbers".to_owned());
+    }
+    Ok(RotationMatrix { values })
+}
+
+fn csv_rows(content: &[u8]) -> Result<Rows, ImportError> {
+    let content = std::str::from_utf8(content)
+        .map_err(|_| ImportError::InvalidFile("CSV files have to be UTF-8".to_owned()))?;
+    let mut rows = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let cells: Vec<_> = line
+            .split(',')
+            .map(|cell| cell.trim().trim_matches('"'))
+            .collect();
+        // A header has no numbers at all.
+        if rows.is_empty() && cells.iter().all(|cell| cell.parse::<f64>().is_err()) {
+            continue;
+        }
+        let values = cells
+            .iter()
+            .map(|cell| cell.parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid number in '{}'", line.trim()));
+        rows.push((index + 1, values));
+    }
+    Ok(rows)
+}
+
+fn json_rows(content: &[u8]) -> Result<Rows, ImportError> {
+    let items: Vec<serde_json::Value> = serde_json::from_slice(content).map_err(|error| {
+        ImportError::InvalidFile(format!("expected a JSON array of matrices: {}", error))
+    })?;
+    Ok(items
+        .into_iter()
+        .enumerate()
+        .map(|(index, item)| (index + 1, json_values(item)))
+        .collect())
+}
+
+fn json_values(item: serde_json::Value) -> Result<Vec<f64>, String> {
+    let item = match item {
+        serde_json::Value::Object(mut object) => object
+            .remove("values")
+            .ok_or_else(|| "object without 'values'".to_owned())?,
+        item => item,
+    };
+    let numbers = match item {
+        serde_json::Value::Array(numbers) => numbers,
+        _ => return Err("expected an array".to_owned()),
+    };
+    let mut values = Vec::with_capacity(9);
+    for number in numbers {
+        match number {
+            // Nested rows of a 3x3 matrix.
+            serde_json::Value::Array(row) => {
+                for number in row {
+                    values.push(number.as_f64().ok_or("expected numbers")?);
+                }
+            }
+            number => values.push(number.as_f64().ok_or("expected numbers")?),
+        }
+    }
+    Ok(values)
+}
+
+fn npy_rows(content: &[u8]) -> Result<Rows, ImportError> {
+    let invalid = |reason: &str| ImportError::InvalidFile(format!(".npy: {}", reason));
+    if !content.starts_with(NPY_MAGIC) || content.len() < 10 {
+        return Err(invalid("not a NumPy array file"));
+    }
+    // Version 1 has a 2 byte header length, later versions 4 bytes.
+    let (header_start, header_len) = match content[6] {
+        1 => (
+            10,
+            usize::from(u16::from_le_bytes([content[8], content[9]])),
+        ),
+        2 | 3 if content.len() >= 12 => (
+            12,
+            u32::from_le_bytes([content[8], content[9], content[10], content[11]]) as usize,
+        ),
+        _ => return Err(invalid("unsupported format version")),
+    };
+    let data_start = header_start + header_len;
+    let header = content
+        .get(header_start..data_start)
+        .and_then(|header| std::str::from_utf8(header).ok())
+        .ok_or_else(|| invalid("invalid header"))?;
+
+    let big_endian = match header_value(header, "descr") {
+        Some("'<f8'") => false,
+        Some("'>f8'") => true,
+        _ => return Err(invalid("the array has to be float64")),
+    };
+    if header_value(header, "fortran_order") != Some("False") {
+        return Err(invalid("Fortran order isn't supported"));
+    }
+    let shape = header_value(header, "shape")
+        .map(|shape| {
+            shape
+                .trim_matches(|c| c == '(' || c == ')')
+                .split(',')
+                .map(str::trim)
+                .filter(|dimension| !dimension.is_empty())
+                .map(str::parse::<usize>)
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .and_then(Result::ok);
+    let count = match shape.as_deref() {
+        Some([count, 3, 3]) | Some([count, 9]) => *count,
+        _ => return Err(invalid("the shape has to be (n, 3, 3) or (n, 9)")),
+    };
+
+    let data_len = count
+        .checked_mul(9 * 8)
+        .ok_or_else(|| invalid("the shape is too large"))?;
+    let data = &content[data_start..];
+    if data.len() != data_len {
+        return Err(invalid("the data doesn't match the shape"));
+    }
+    Ok(data
+        .chunks_exact(9 * 8)
+        .enumerate()
+        .map(|(index, matrix)| {
+            let values = matrix
+                .chunks_exact(8)
+                .map(|bytes| {
+                    let bytes = bytes.try_into().expect("8 bytes");
+                    if big_endian {
+                        f64::from_be_bytes(bytes)
+                    } else {
+                        f64::from_le_bytes(bytes)
+                    }
+                })
+                .collect();
+            (index + 1, Ok(values))
+        })
+        .collect())
+}
+
+// The value of `key` in the Python dict literal of a `.npy` header.
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+    let rest = header[start..].trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')')? + 1
+    } else {
+        rest.find([',', '}'])?
+    };
+    Some(rest[..end].trim())
+}
+
+// ---- Export ----
+
+/// What a file with conversion results contains.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultKind {
+    #[default]
+    Quaternions,
+    Matrices,
+    EulerAngles,
+}
+
+impl ResultKind {
+    /// In the field order of the `shared` types, the matrix row-major.
+    pub const fn columns(self) -> &'static [&'static str] {
+        match self {
+            Self::Quaternions => &["x", "y", "z", "w"],
+            Self::Matrices => &[
+                "m11", "m12", "m13", "m21", "m22", "m23", "m31", "m32", "m33",
+            ],
+            Self::EulerAngles => &["roll", "pitch", "yaw"],
+        }
+    }
+
+    pub const fn file_stem(self) -> &'static str {
+        match self {
+            Self::Quaternions => "quaternions",
+            Self::Matrices => "matrices",
+            Self::EulerAngles => "euler_angles",
+        }
+    }
+
+    fn npy_shape(self, count: usize) -> Vec<usize> {
+        match self {
+            Self::Quaternions => vec![count, 4],
+            Self::Matrices => vec![count, 3, 3],
+            Self::EulerAngles => vec![count, 3],
+        }
+    }
+
+    fn values(self, quaternion: &Quaternion) -> Vec<f64> {
+        match self {
+            Self::Quaternions => {
+                let q = quaternion;
+                vec![q.x, q.y, q.z, q.w]
+            }
+            Self::Matrices => conversion::to_matrix(quaternion).values.to_vec(),
+            Self::EulerAngles => {
+                let angles = conversion::to_euler_angles(quaternion);
+                vec![angles.roll, angles.pitch, angles.yaw]
+            }
+        }
+    }
+}
+
+pub struct CsvOptions {
+    // Indices into `ResultKind::columns`.
+    columns: Vec<usize>,
+    precision: Option<usize>,
+}
+
+impl CsvOptions {
+    /// All columns with the shortest exact number format.
+    pub fn all(kind: ResultKind) -> Self {
+        Self {
+            columns: (0..kind.columns().len()).collect(),
+            precision: None,
+        }
+    }
+
+    /// `columns` are comma separated names of `kind.columns()` in any order,
+    /// `precision` the number of decimal places.
+    pub fn new(
+        kind: ResultKind,
+        columns: Option<&str>,
+        precision: Option<usize>,
+    ) -> Result<Self, ExportError> {
+        if let Some(precision) = precision.filter(|precision| *precision > MAX_PRECISION) {
+            return Err(ExportError::InvalidPrecision(precision));
+        }
+        let columns = match columns {
+            Some(columns) => columns
+                .split(',')
+                .map(str::trim)
+                .map(|name| {
+                    kind.columns()
+                        .iter()
+                        .position(|column| *column == name)
+                        .ok_or_else(|| ExportError::UnknownColumn(name.to_owned()))
+                })
+                .collect::<Result<_, _>>()?,
+            None => Self::all(kind).columns,
+        };
+        Ok(Self { columns, precision })
+    }
+}
+
+/// The results as CSV with a header, a JSON array of the `shared` type
+/// or a little-endian float64 `.npy` array, e.g. of shape `(n, 4)` for quaternions.
+pub fn write_results(
+    format: MatrixFileFormat,
+    kind: ResultKind,
+    quaternions: &[Quaternion],
+    csv_options: &CsvOptions,
+) -> Vec<u8> {
+    match format {
+        MatrixFileFormat::Csv => {
+            let names: Vec<_> = csv_options
+                .columns
+                .iter()
+                .map(|index| kind.columns()[*index])
+                .collect();
+            let mut csv = names.join(",");
+            csv.push('\n');
+            for quaternion in quaternions {
+                let values = kind.values(quaternion);
+                let cells: Vec<_> = csv_options
+                    .columns
+                    .iter()
+                    .map(|index| match csv_options.precision {
+                        Some(precision) => format!("{:.*}", precision, values[*index]),
+                        None => values[*index].to_string(),
+                    })
+                    .collect();
+                writeln!(&mut csv, "{}", cells.join(",")).unwrap();
+            }
+            csv.into_bytes()
+        }
+        MatrixFileFormat::Json => {
+            let json = match kind {
+                ResultKind::Quaternions => serde_json::to_vec(quaternions),
+                ResultKind::Matrices => serde_json::to_vec(
+                    &quaternions
+                        .iter()
+                        .map(conversion::to_matrix)
+                        .collect::<Vec<_>>(),
+                ),
+                ResultKind::EulerAngles => serde_json::to_vec(
+                    &quaternions
+                        .iter()
+                        .map(conversion::to_euler_angles)
+                        .collect::<Vec<_>>(),
+                ),
+            };
+            json.expect("serialize results")
+        }
+        MatrixFileFormat::Npy => {
+            let values: Vec<_> = quaternions
+                .iter()
+                .flat_map(|quaternion| kind.values(quaternion))
+                .collect();
+            write_npy(&values, &kind.npy_shape(quaternions.len()))
+        }
+    }
+}
+
+fn write_npy(values: &[f64], shape: &[usize]) -> Vec<u8> {
+    let shape: Vec<_> = shape.iter().map(ToString::to_string).collect();
+    let mut header = format!(
+        "{{'descr': '<f8', 'fortran_order': False, 'shape': ({}), }}",
+        shape.join(", ")
+    );
+    // The data starts 64-byte aligned, the header ends with a newline.
+    let unpadded = NPY_MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut npy = Vec::with_capacity(NPY_MAGIC.len() + 4 + header.len() + values.len() * 8);
+    npy.extend_from_slice(NPY_MAGIC);
+    npy.extend_from_slice(&[1, 0]);
+    npy.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    npy.extend_from_slice(header.as_bytes());
+    for value in values {
+        npy.extend_from_slice(&value.to_le_bytes());
+    }
+    npy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrices(format: MatrixFileFormat, content: &[u8]) -> Vec<[f64; 9]> {
+        read_matrices(format, content)
+            .expect("valid file")
+            .into_iter()
+            .map(|matrix| matrix.values)
+            .collect()
+    }
+
+    fn row_errors(format: MatrixFileFormat, content: &[u8]) -> Vec<(usize, String)> {
+        match read_matrices(format, content) {
+            Err(ImportError::InvalidRows(errors)) => errors
+                .into_iter()
+                .map(|error| (error.row, error.message))
+                .collect(),
+            result => panic!("expected invalid rows, got {:?}", result),
+        }
+    }
+
+    // The magic and header of a version 1 `.npy` file of shape `(count, 9)`.
+    fn npy_header(descr: &str, count: usize) -> Vec<u8> {
+        let header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': ({}, 9), }}\n",
+            descr, count
+        );
+        let mut npy = NPY_MAGIC.to_vec();
+        npy.extend_from_slice(&[1, 0]);
+        npy.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        npy.extend_from_slice(header.as_bytes());
+        npy
+    }
+
+    fn npy(descr: &str, values: &[f64], big_endian: bool) -> Vec<u8> {
+        let mut npy = npy_header(descr, values.len() / 9);
+        for value in values {
+            if big_endian {
+                npy.extend_from_slice(&value.to_be_bytes());
+            } else {
+                npy.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        npy
+    }
+
+    const IDENTITY: [f64; 9] = [1., 0., 0., 0., 1., 0., 0., 0., 1.];
+    const ROTATION: [f64; 9] = [0., -1., 0., 1., 0., 0., 0., 0., 1.];
+
+    #[test]
+    fn csv_skips_the_header() {
+        let csv = b"m11,m12,m13,m21,m22,m23,m31,m32,m33\n1,0,0,0,1,0,0,0,1\n\n0,-1,0,1,0,0,0,0,1\n";
+        assert_eq!(
+            matrices(MatrixFileFormat::Csv, csv),
+            vec![IDENTITY, ROTATION]
+        );
+
+        let csv = b"1,0,0,0,1,0,0,0,1\n";
+        assert_eq!(matrices(MatrixFileFormat::Csv, csv), vec![IDENTITY]);
+    }
+
+    #[test]
+    fn csv_row_errors_have_line_numbers() {
+        let csv =
+            b"m11,m12,m13,m21,m22,m23,m31,m32,m33\n1,0,0,0,1,0,0,0,1\n1,0,x,0,1,0,0,0,1\n1,0,0\n";
+        assert_eq!(
+            row_errors(MatrixFileFormat::Csv, csv),
+            vec![
+                (3, "invalid number in '1,0,x,0,1,0,0,0,1'".to_owned()),
+                (4, "expected 9 values, found 3".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn json_flat_nested_and_object_rows() {
+        let json = br#"[
+            [1, 0, 0, 0, 1, 0, 0, 0, 1],
+            [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+            {"values": [[1, 0, 0], [0, 1, 0], [0, 0, 1]]}
+        ]"#;
+        assert_eq!(
+            matrices(MatrixFileFormat::Json, json),
+            vec![IDENTITY, ROTATION, IDENTITY]
+        );
+    }
+
+    #[test]
+    fn json_row_errors() {
+        let json = br#"[[1, 0, 0, 0, 1, 0, 0, 0, 1], {"rows": []}, [1, "0"], 5, [[1, 0], [0, 1]]]"#;
+        assert_eq!(
+            row_errors(MatrixFileFormat::Json, json),
+            vec![
+                (2, "object without 'values'".to_owned()),
+                (3, "expected numbers".to_owned()),
+                (4, "expected an array".to_owned()),
+                (5, "expected 9 values, found 4".to_owned()),
+            ]
+        );
+        assert!(matches!(
+            read_matrices(MatrixFileFormat::Json, b"{}"),
+            Err(ImportError::InvalidFile(_))
+        ));
+    }
+
+    #[test]
+    fn npy_little_and_big_endian() {
+        let values = [IDENTITY, ROTATION].concat();
+        for &(descr, big_endian) in &[("<f8", false), (">f8", true)] {
+            let npy = npy(descr, &values, big_endian);
+            assert_eq!(
+                matrices(MatrixFileFormat::Npy, &npy),
+                vec![IDENTITY, ROTATION],
+                "{}",
+                descr
+            );
+        }
+    }
+
+    #[test]
+    fn npy_invalid_files() {
+        let invalid = |npy: &[u8]| {
+            matches!(
+                read_matrices(MatrixFileFormat::Npy, npy),
+                Err(ImportError::InvalidFile(_))
+            )
+        };
+        assert!(invalid(b"not numpy"));
+        assert!(invalid(&npy("<f4", &IDENTITY, false)));
+        // Data of one matrix missing.
+        let npy_file = npy("<f8", &[IDENTITY, ROTATION].concat(), false);
+        assert!(invalid(&npy_file[..npy_file.len() - 72]));
+
+        // The byte size of the shape overflows `usize`.
+        assert!(invalid(&npy_header("<f8", usize::MAX / 8)));
+    }
+
+    #[test]
+    fn npy_row_errors() {
+        let mut values = [IDENTITY, IDENTITY].concat();
+        values[12] = f64::NAN;
+        assert_eq!(
+            row_errors(MatrixFileFormat::Npy, &npy("<f8", &values, false)),
+            vec![(2, "values have to be finite numbers".to_owned())]
+        );
+    }
+
+    #[test]
+    fn write_npy_round_trip() {
+        let values = [IDENTITY, ROTATION].concat();
+        let npy = write_npy(&values, &[2, 3, 3]);
+        // The data is 64-byte aligned.
+        assert_eq!((npy.len() - values.len() * 8) % 64, 0);
+        assert_eq!(
+            matrices(MatrixFileFormat::Npy, &npy),
+            vec![IDENTITY, ROTATION]
+        );
+    }
+}
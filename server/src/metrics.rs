@@ -0,0 +1,222 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{Error, HttpResponse};
+use futures::future::{self, Future, LocalBoxFuture, Ready};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, TextEncoder,
+};
+use std::convert::TryFrom;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+// Label for requests without a matching route, so unknown paths
+// don't create new time series.
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+lazy_static! {
+    static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "http_requests_total",
+        "Number of HTTP requests by route and status.",
+        &["method", "route", "status"]
+    )
+    .expect("register http_requests_total");
+    static ref HTTP_REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "http_request_duration_seconds",
+        "HTTP request latencies by route.",
+        &["method", "route"]
+    )
+    .expect("register http_request_duration_seconds");
+    static ref COUNT_ACTOR_MAILBOX_DEPTH: IntGauge = register_int_gauge!(
+        "count_actor_mailbox_depth",
+        "Messages sent to CountActor and not handled yet."
+    )
+    .expect("register count_actor_mailbox_depth");
+    static ref COUNTER_VALUE: IntGaugeVec = register_int_gauge_vec!(
+        "counter_value",
+        "Current value of the CountActor counters.",
+        &["counter"]
+    )
+    .expect("register counter_value");
+    static ref FORM_RECEIVED_BYTES: IntCounter = register_int_counter!(
+        "form_received_bytes_total",
+        "Multipart bytes received by /api/form."
+    )
+    .expect("register form_received_bytes_total");
+    static ref MATRIX_CONVERSION_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "matrix_conversion_errors_total",
+        "Rejected /api/matrix requests by reason.",
+        &["reason"]
+    )
+    .expect("register matrix_conversion_errors_total");
+    static ref RATE_LIMITED_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "rate_limited_requests_total",
+        "Requests rejected with 429 by route.",
+        &["route"]
+    )
+    .expect("register rate_limited_requests_total");
+    static ref CHAOS_FAULTS: IntCounterVec = register_int_counter_vec!(
+        "chaos_faults_total",
+        "Faults injected by the chaos middleware by kind.",
+        &["kind"]
+    )
+    .expect("register chaos_faults_total");
+}
+
+// ---- Recording ----
+
+/// Counts the request in `count_actor_mailbox_depth` until it's resolved or dropped.
+pub async fn count_actor_mailbox<F: Future>(request: F) -> F::Output {
+    struct InMailbox;
+
+    impl Drop for InMailbox {
+        fn drop(&mut self) {
+            COUNT_ACTOR_MAILBOX_DEPTH.dec();
+        }
+    }
+
+    COUNT_ACTOR_MAILBOX_DEPTH.inc();
+    let _in_mailbox = InMailbox;
+    request.await
+}
+
+pub fn set_counter_value(counter: &str, value: u64) {
+    COUNTER_VALUE
+        .with_label_values(&[counter])
+        .set(i64::try_from(value).unwrap_or(i64::MAX));
+}
+
+pub fn remove_counter_value(counter: &str) {
+    // The counter may not have a time series yet.
+    let _ = COUNTER_VALUE.remove_label_values(&[counter]);
+}
+
+pub fn add_form_received_bytes(bytes: usize) {
+    FORM_RECEIVED_BYTES.inc_by(bytes as i64);
+}
+
+pub fn inc_matrix_conversion_errors(reason: &str) {
+    MATRIX_CONVERSION_ERRORS.with_label_values(&[reason]).inc();
+}
+
+pub fn inc_rate_limited_requests(route: Option<&str>) {
+    RATE_LIMITED_REQUESTS
+        .with_label_values(&[route.unwrap_or(UNMATCHED_ROUTE)])
+        .inc();
+}
+
+pub fn inc_chaos_faults(kind: &str) {
+    CHAOS_FAULTS.with_label_values(&[kind]).inc();
+}
+
+// ---- Endpoint ----
+
+/// Renders all metrics in the Prometheus text format.
+pub async fn metrics() -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(()) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(error) => HttpResponse::InternalServerError().body(error.to_string()),
+    }
+}
+
+// ---- Middleware ----
+
+/// Records `http_requests_total` and `http_request_duration_seconds`
+/// labeled by the route pattern.
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S> for RequestMetrics
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestMetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(RequestMetricsMiddleware { service })
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestMetricsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let method = req.method().to_string();
+        let route = req
+            .match_pattern()
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_owned());
+        let started = Instant::now();
+        let response = self.service.call(req);
+
+        Box::pin(async move {
+            let response = response.await;
+            HTTP_REQUEST_DURATION
+                .with_label_values(&[&method, &route])
+                .observe(started.elapsed().as_secs_f64());
+            let status = match &response {
+                Ok(response) => response.status(),
+                Err(error) => error.as_response_error().status_code(),
+            };
+            HTTP_REQUESTS
+                .with_label_values(&[&method, &route, status.as_str()])
+                .inc();
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn requests_are_counted_by_route() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestMetrics)
+                .route("/items/{id}", web::get().to(HttpResponse::Ok))
+                .route("/metrics", web::get().to(metrics)),
+        )
+        .await;
+
+        let request = test::TestRequest::get().uri("/items/7").to_request();
+        test::call_service(&mut app, request).await;
+
+        let request = test::TestRequest::get().uri("/metrics").to_request();
+        let body = test::read_response(&mut app, request).await;
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body
+            .contains(r#"http_requests_total{method="GET",route="/items/{id}",status="200"} 1"#));
+        assert!(body.contains(
+            r#"http_request_duration_seconds_count{method="GET",route="/items/{id}"} 1"#
+        ));
+    }
+}
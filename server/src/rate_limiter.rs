@@ -0,0 +1,498 @@
+use actix::prelude::*;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{header, HeaderMap, StatusCode};
+use actix_web::{error, Error, HttpResponse, ResponseError};
+use futures::future::{self, LocalBoxFuture, Ready};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{self, Poll};
+use std::time::{Duration, Instant};
+
+use crate::auth::{Auth, Identity};
+use crate::config::{BucketConfig, RateLimitConfig};
+use crate::metrics;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+const FORWARDED_HEADER: &str = "forwarded";
+const X_FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+// ---- Actor ----
+
+struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    fn full(limit: &BucketConfig) -> Self {
+        Self {
+            tokens: f64::from(limit.burst),
+            updated_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, limit: &BucketConfig) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(f64::from(limit.burst));
+        self.updated_at = now;
+    }
+
+    /// Returns how long until the next token is available, if there is none.
+    fn check(&mut self, limit: &BucketConfig) -> Result<(), Duration> {
+        self.refill(limit);
+        if self.tokens >= 1. {
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1. - self.tokens) / limit.per_second,
+            ))
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ClientKey {
+    Subject(String),
+    Ip(IpAddr),
+}
+
+/// Keeps a token bucket per client (authenticated subject or IP) and limited route prefix.
+/// Routes without their own limit share the client's default bucket.
+pub struct RateLimitActor {
+    config: RateLimitConfig,
+    // `None` route is the default bucket.
+    buckets: HashMap<(ClientKey, Option<String>), TokenBucket>,
+}
+
+impl RateLimitActor {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: HashMap::new(),
+        }
+    }
+
+    // The longest configured prefix of `path`.
+    fn route(&self, path: &str) -> Option<String> {
+        self.config
+            .routes
+            .keys()
+            .filter(|prefix| path.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len())
+            .cloned()
+    }
+
+    fn limit(&self, route: Option<&String>) -> &BucketConfig {
+        route
+            .and_then(|route| self.config.routes.get(route))
+            .unwrap_or(&self.config.default)
+    }
+
+    // Full buckets behave like new ones, so they can be dropped.
+    fn remove_full_buckets(&mut self) {
+        let config = &self.config;
+        self.buckets.retain(|(_, route), bucket| {
+            let limit = route
+                .as_ref()
+                .and_then(|route| config.routes.get(route))
+                .unwrap_or(&config.default);
+            bucket.refill(limit);
+            bucket.tokens < f64::from(limit.burst)
+        });
+    }
+}
+
+impl Actor for RateLimitActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_interval(CLEANUP_INTERVAL, |actor, _| actor.remove_full_buckets());
+    }
+}
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub struct RateLimitError {
+    retry_after: Duration,
+}
+
+impl RateLimitError {
+    // `Retry-After` has a resolution of seconds.
+    fn retry_after_secs(&self) -> u64 {
+        (self.retry_after.as_secs_f64().ceil() as u64).max(1)
+    }
+}
+
+impl fmt::Display for RateLimitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Too many requests, retry in {}s",
+            self.retry_after_secs()
+        )
+    }
+}
+
+impl ResponseError for RateLimitError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::TOO_MANY_REQUESTS
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .header(header::RETRY_AFTER, self.retry_after_secs())
+            .body(self.to_string())
+    }
+}
+
+// ---- Messages ----
+
+#[derive(Clone)]
+pub struct MsgAcquire {
+    // A token is taken from the bucket of every client.
+    clients: Vec<ClientKey>,
+    path: String,
+}
+
+impl MsgAcquire {
+    /// Requests with valid credentials are limited by their `Identity::subject`.
+    /// Users are limited by their IP too, so registering more accounts doesn't raise the limit.
+    pub fn new(identity: Option<&Identity>, ip: IpAddr, path: &str) -> Self {
+        let clients = match identity {
+            Some(identity) if identity.username().is_some() => {
+                vec![
+                    ClientKey::Subject(identity.subject.clone()),
+                    ClientKey::Ip(ip),
+                ]
+            }
+            Some(identity) => vec![ClientKey::Subject(identity.subject.clone())],
+            None => vec![ClientKey::Ip(ip)],
+        };
+        Self {
+            clients,
+            path: path.to_owned(),
+        }
+    }
+}
+
+impl Message for MsgAcquire {
+    type Result = Result<(), RateLimitError>;
+}
+
+// ---- Handlers ----
+
+impl Handler<MsgAcquire> for RateLimitActor {
+    type Result = Result<(), RateLimitError>;
+
+    fn handle(&mut self, msg: MsgAcquire, _: &mut Context<Self>) -> Self::Result {
+        let route = self.route(&msg.path);
+        let limit = *self.limit(route.as_ref());
+        let keys: Vec<_> = msg
+            .clients
+            .into_iter()
+            .map(|client| (client, route.clone()))
+            .collect();
+
+        let buckets = &mut self.buckets;
+        let retry_after = keys
+            .iter()
+            .filter_map(|key| {
+                buckets
+                    .entry(key.clone())
+                    .or_insert_with(|| TokenBucket::full(&limit))
+                    .check(&limit)
+                    .err()
+            })
+            .max();
+        if let Some(retry_after) = retry_after {
+            return Err(RateLimitError { retry_after });
+        }
+        for key in &keys {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.;
+            }
+        }
+        Ok(())
+    }
+}
+
+// ---- Client IP ----
+
+/// The peer IP, or the client IP forwarded by the peer if it's one of the `trusted_proxies`.
+/// Forwarded addresses are read from the right, every trusted proxy appends
+/// the address it got the request from, the ones left of the first untrusted address
+/// may be sent by the client.
+pub fn client_ip(
+    peer_addr: Option<SocketAddr>,
+    headers: &HeaderMap,
+    trusted_proxies: &[IpAddr],
+) -> IpAddr {
+    let mut client = peer_addr.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
+    for forwarded in forwarded_for(headers).into_iter().rev() {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        // Obfuscated and unknown addresses end the chain at the last trusted proxy.
+        match forwarded {
+            Some(ip) => client = ip,
+            None => break,
+        }
+    }
+    client
+}
+
+// The `for` addresses of the `Forwarded` header or else of `X-Forwarded-For`, oldest first.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name: &str| {
+        let lines: Vec<_> = headers.get_all(name).collect();
+        // `HeaderMap` doesn't keep the order of repeated header lines,
+        // so their addresses can't be told apart from the client's.
+        if lines.len() > 1 {
+            return vec!["unknown"];
+        }
+        lines
+            .into_iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect()
+    };
+    let forwarded = values(FORWARDED_HEADER);
+    if !forwarded.is_empty() {
+        forwarded
+            .iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node))
+            })
+            .collect()
+    } else {
+        values(X_FORWARDED_FOR_HEADER)
+            .iter()
+            .map(|node| parse_node(node))
+            .collect()
+    }
+}
+
+// `192.0.2.1`, `"192.0.2.1:80"`, `2001:db8::1` or `"[2001:db8::1]:80"`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| node.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+// ---- Middleware ----
+
+/// Rejects requests over the client's limit with `429 Too Many Requests`
+/// and a `Retry-After` header. Clients are identified by `auth`,
+/// also on routes that don't require credentials, or by `client_ip`.
+pub struct RateLimiter {
+    actor: Addr<RateLimitActor>,
+    auth: Arc<Auth>,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl RateLimiter {
+    pub fn new(actor: Addr<RateLimitActor>, auth: Arc<Auth>, trusted_proxies: Vec<IpAddr>) -> Self {
+        Self {
+            actor,
+            auth,
+            trusted_proxies,
+        }
+    }
+}
+
+impl<S, B> Transform<S> for RateLimiter
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimiterMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(RateLimiterMiddleware {
+            // Shared with the response future, which calls the service
+            // only after the actor has answered.
+            service: Rc::new(RefCell::new(service)),
+            actor: self.actor.clone(),
+            auth: self.auth.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+        })
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    actor: Addr<RateLimitActor>,
+    auth: Arc<Auth>,
+    trusted_proxies: Vec<IpAddr>,
+}
+
+impl<S, B> Service for RateLimiterMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let route = req.match_pattern();
+        let identity = self.auth.authenticate(&req).ok();
+        let ip = client_ip(req.peer_addr(), req.headers(), &self.trusted_proxies);
+        let acquire = self
+            .actor
+            .send(MsgAcquire::new(identity.as_ref(), ip, req.path()));
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            if let Err(error) = acquire.await.map_err(error::ErrorInternalServerError)? {
+                metrics::inc_rate_limited_requests(route.as_deref());
+                return Ok(req.error_response(error));
+            }
+            let response = service.borrow_mut().call(req);
+            response.await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::HeaderValue;
+
+    fn acquire(identity: Option<Identity>, ip: [u8; 4]) -> MsgAcquire {
+        MsgAcquire::new(identity.as_ref(), IpAddr::from(ip), "/api/counters")
+    }
+
+    fn start() -> Addr<RateLimitActor> {
+        let config = RateLimitConfig {
+            default: BucketConfig {
+                per_second: 0.001,
+                burst: 1,
+            },
+            routes: Default::default(),
+            ..RateLimitConfig::default()
+        };
+        RateLimitActor::new(config).start()
+    }
+
+    async fn allowed(actor: &Addr<RateLimitActor>, msg: MsgAcquire) -> bool {
+        actor.send(msg).await.unwrap().is_ok()
+    }
+
+    #[actix_rt::test]
+    async fn buckets_by_subject_or_ip() {
+        let actor = start();
+        let api_key = || Some(Identity::api_key("ci", Vec::new()));
+
+        assert!(allowed(&actor, acquire(api_key(), [10, 0, 0, 1])).await);
+        // The same API key from another IP shares the bucket.
+        assert!(!allowed(&actor, acquire(api_key(), [10, 0, 0, 2])).await);
+        // API keys don't use the bucket of their IP.
+        assert!(allowed(&actor, acquire(None, [10, 0, 0, 1])).await);
+        assert!(!allowed(&actor, acquire(None, [10, 0, 0, 1])).await);
+        assert!(allowed(&actor, acquire(None, [10, 0, 0, 2])).await);
+    }
+
+    #[actix_rt::test]
+    async fn users_share_the_bucket_of_their_ip() {
+        let actor = start();
+        let user = |name| Some(Identity::user(name, Vec::new()));
+
+        assert!(allowed(&actor, acquire(user("alice"), [10, 0, 0, 1])).await);
+        // Another account from the same IP.
+        assert!(!allowed(&actor, acquire(user("bob"), [10, 0, 0, 1])).await);
+        assert!(!allowed(&actor, acquire(None, [10, 0, 0, 1])).await);
+        // A user named like an API key has its own bucket.
+        assert!(allowed(&actor, acquire(user("ci"), [10, 0, 0, 2])).await);
+        assert!(allowed(&actor, acquire(user("bob"), [10, 0, 0, 3])).await);
+        // The rejected request of bob didn't take a token from his bucket.
+        assert!(!allowed(&actor, acquire(user("bob"), [10, 0, 0, 4])).await);
+    }
+
+    fn forwarded_ip(peer: &str, headers: &[(&'static str, &'static str)]) -> IpAddr {
+        let trusted_proxies = ["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.append(
+                header::HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        client_ip(Some(peer.parse().unwrap()), &header_map, &trusted_proxies)
+    }
+
+    #[test]
+    fn client_ip_is_forwarded_by_trusted_proxies_only() {
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let xff = |value| [(X_FORWARDED_FOR_HEADER, value)];
+
+        assert_eq!(forwarded_ip("192.0.2.1:80", &[]), ip("192.0.2.1"));
+        assert_eq!(
+            forwarded_ip("192.0.2.1:80", &xff("198.51.100.1")),
+            ip("192.0.2.1")
+        );
+        assert_eq!(forwarded_ip("10.0.0.1:80", &[]), ip("10.0.0.1"));
+        assert_eq!(
+            forwarded_ip("10.0.0.1:80", &xff("198.51.100.1")),
+            ip("198.51.100.1")
+        );
+        // Addresses left of the first untrusted one may be set by the client.
+        assert_eq!(
+            forwarded_ip("10.0.0.1:80", &xff("203.0.113.9, 198.51.100.1, 10.0.0.2")),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            forwarded_ip("10.0.0.1:80", &xff("198.51.100.1, unknown")),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            forwarded_ip(
+                "10.0.0.1:80",
+                &[
+                    (
+                        FORWARDED_HEADER,
+                        r#"for=198.51.100.1;proto=https, For="[2001:db8::1]:4711""#
+                    ),
+                    (X_FORWARDED_FOR_HEADER, "203.0.113.9"),
+                ]
+            ),
+            ip("2001:db8::1")
+        );
+        assert_eq!(
+            forwarded_ip(
+                "10.0.0.1:80",
+                &[
+                    (X_FORWARDED_FOR_HEADER, "198.51.100.1"),
+                    (X_FORWARDED_FOR_HEADER, "203.0.113.9"),
+                ]
+            ),
+            ip("10.0.0.1")
+        );
+    }
+}
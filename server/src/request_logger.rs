@@ -0,0 +1,153 @@
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::{HeaderName, HeaderValue};
+use actix_web::Error;
+use futures::future::{self, LocalBoxFuture, Ready};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tracing::{error, info, info_span};
+use tracing_futures::Instrument;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longer or non-ASCII ids sent by clients are replaced by a fresh one.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+// ---- Middleware ----
+
+/// Logs every request with its method, path, status and latency
+/// inside a span carrying the request id.
+/// The id is taken from the `x-request-id` request header or generated
+/// and it's always returned in the `x-request-id` response header.
+pub struct RequestLogger;
+
+impl<S, B> Transform<S> for RequestLogger
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestLoggerMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        future::ok(RequestLoggerMiddleware { service })
+    }
+}
+
+pub struct RequestLoggerMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestLoggerMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let request_id = request_id(&req);
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            path = %req.path(),
+        );
+        let started = Instant::now();
+        let response = span.in_scope(|| self.service.call(req));
+
+        Box::pin(
+            async move {
+                let latency_ms = |started: Instant| started.elapsed().as_secs_f64() * 1000.;
+                match response.await {
+                    Ok(mut response) => {
+                        info!(
+                            status = response.status().as_u16(),
+                            latency_ms = latency_ms(started),
+                            "request finished"
+                        );
+                        if let Ok(value) = HeaderValue::from_str(&request_id) {
+                            response
+                                .headers_mut()
+                                .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+                        }
+                        Ok(response)
+                    }
+                    Err(err) => {
+                        error!(error = %err, latency_ms = latency_ms(started), "request failed");
+                        Err(err)
+                    }
+                }
+            }
+            .instrument(span),
+        )
+    }
+}
+
+fn request_id(req: &ServiceRequest) -> String {
+    req.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LEN
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        })
+        .map_or_else(|| Uuid::new_v4().to_string(), ToOwned::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn request_id_is_returned() {
+        let mut app = test::init_service(
+            App::new()
+                .wrap(RequestLogger)
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let response_id = |response: &ServiceResponse| {
+            response
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .map(|value| value.to_str().unwrap().to_owned())
+        };
+
+        let request = test::TestRequest::get()
+            .header(REQUEST_ID_HEADER, "client-id.1")
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+        assert_eq!(response_id(&response).as_deref(), Some("client-id.1"));
+
+        let request = test::TestRequest::get()
+            .header(REQUEST_ID_HEADER, "no spaces")
+            .to_request();
+        let response = test::call_service(&mut app, request).await;
+        let generated = response_id(&response).unwrap();
+        assert!(Uuid::parse_str(&generated).is_ok());
+
+        let request = test::TestRequest::get().to_request();
+        let response = test::call_service(&mut app, request).await;
+        let generated_again = response_id(&response).unwrap();
+        assert!(Uuid::parse_str(&generated_again).is_ok());
+        assert_ne!(generated, generated_again);
+    }
+}
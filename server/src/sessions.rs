@@ -0,0 +1,134 @@
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::ServiceRequest;
+use actix_web::http::Method;
+use actix_web::HttpMessage;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::auth::{AuthError, Authenticator, Identity};
+
+pub const SESSION_COOKIE: &str = "session";
+pub const CSRF_HEADER: &str = "x-csrf-token";
+
+struct Session {
+    username: String,
+    csrf_token: String,
+    expires_at: Instant,
+}
+
+/// Sessions of logged-in users by the id in the `session` cookie.
+/// They're kept in memory, so users have to log in again after a restart.
+pub struct Sessions {
+    sessions: RwLock<HashMap<String, Session>>,
+    ttl: Duration,
+    scopes: Vec<String>,
+    secure_cookie: bool,
+}
+
+impl Sessions {
+    /// Users get `scopes`, `secure_cookie` should be set when serving HTTPS.
+    pub fn new(ttl: Duration, scopes: Vec<String>, secure_cookie: bool) -> Self {
+        Self {
+            sessions: RwLock::new(HashMap::new()),
+            ttl,
+            scopes,
+            secure_cookie,
+        }
+    }
+
+    /// Returns the session cookie and the CSRF token of the new session.
+    pub fn create(&self, username: String) -> (Cookie<'static>, String) {
+        let id = random_token();
+        let csrf_token = random_token();
+        let now = Instant::now();
+
+        let mut sessions = self.sessions.write().expect("lock sessions");
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(
+            id.clone(),
+            Session {
+                username,
+                csrf_token: csrf_token.clone(),
+                expires_at: now + self.ttl,
+            },
+        );
+
+        let cookie = Cookie::build(SESSION_COOKIE, id)
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Strict)
+            .secure(self.secure_cookie)
+            .finish();
+        (cookie, csrf_token)
+    }
+
+    /// The username and CSRF token of the request's session.
+    /// Requests with other methods than `GET` and `HEAD` need the CSRF token
+    /// in the `x-csrf-token` header.
+    pub fn find(
+        &self,
+        req: &impl HttpMessage,
+        method: &Method,
+    ) -> Result<Option<(String, String)>, AuthError> {
+        let id = match req.cookie(SESSION_COOKIE) {
+            Some(cookie) => cookie.value().to_owned(),
+            None => return Ok(None),
+        };
+        let sessions = self.sessions.read().expect("lock sessions");
+        let session = sessions
+            .get(&id)
+            .filter(|session| session.expires_at > Instant::now())
+            .ok_or(AuthError::InvalidSession)?;
+
+        if method != Method::GET && method != Method::HEAD {
+            let csrf_token = req
+                .headers()
+                .get(CSRF_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            if !constant_time_eq(csrf_token, &session.csrf_token) {
+                return Err(AuthError::InvalidCsrfToken);
+            }
+        }
+        Ok(Some((session.username.clone(), session.csrf_token.clone())))
+    }
+
+    pub fn remove(&self, req: &impl HttpMessage) {
+        if let Some(cookie) = req.cookie(SESSION_COOKIE) {
+            self.sessions
+                .write()
+                .expect("lock sessions")
+                .remove(cookie.value());
+        }
+    }
+
+    /// Tells the browser to delete the session cookie.
+    pub fn removal_cookie() -> Cookie<'static> {
+        Cookie::build(SESSION_COOKIE, "").path("/").finish()
+    }
+}
+
+impl Authenticator for Sessions {
+    fn authenticate(&self, req: &ServiceRequest) -> Result<Option<Identity>, AuthError> {
+        Ok(self
+            .find(req, req.method())?
+            .map(|(username, _)| Identity::user(&username, self.scopes.clone())))
+    }
+}
+
+// 256 random bits, hex encoded.
+fn random_token() -> String {
+    let bytes: [u8; 32] = rand::thread_rng().gen();
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Doesn't leak the length of the matching prefix through timing.
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,138 @@
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{http::header, HttpResponse};
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::{Arc, RwLock};
+use tracing::{error, info};
+
+use crate::config::TlsConfig;
+
+// ---- Certificates ----
+
+/// Serves the certificate from `TlsConfig`, `reload` swaps it
+/// without restarting the server.
+pub struct CertResolver {
+    config: TlsConfig,
+    certified_key: RwLock<CertifiedKey>,
+}
+
+impl CertResolver {
+    pub fn load(config: &TlsConfig) -> io::Result<Self> {
+        Ok(Self {
+            certified_key: RwLock::new(load_certified_key(config)?),
+            config: config.clone(),
+        })
+    }
+
+    pub fn reload(&self) -> io::Result<()> {
+        let certified_key = load_certified_key(&self.config)?;
+        *self.certified_key.write().expect("lock certified key") = certified_key;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _: ClientHello) -> Option<CertifiedKey> {
+        self.certified_key.read().ok().map(|key| key.clone())
+    }
+}
+
+fn load_certified_key(config: &TlsConfig) -> io::Result<CertifiedKey> {
+    let invalid_data = |reason: String| io::Error::new(io::ErrorKind::InvalidData, reason);
+
+    let certs = pemfile::certs(&mut BufReader::new(File::open(&config.cert)?))
+        .ok()
+        .filter(|certs| !certs.is_empty())
+        .ok_or_else(|| {
+            invalid_data(format!(
+                "no certificate found in '{}'",
+                config.cert.display()
+            ))
+        })?;
+
+    // PKCS#8 (`BEGIN PRIVATE KEY`) first, then PKCS#1 (`BEGIN RSA PRIVATE KEY`).
+    let key = pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(&config.key)?))
+        .ok()
+        .and_then(|keys| keys.into_iter().next())
+        .or_else(|| {
+            File::open(&config.key)
+                .ok()
+                .and_then(|file| pemfile::rsa_private_keys(&mut BufReader::new(file)).ok())
+                .and_then(|keys| keys.into_iter().next())
+        })
+        .ok_or_else(|| {
+            invalid_data(format!(
+                "no private key found in '{}'",
+                config.key.display()
+            ))
+        })?;
+    let signing_key = sign::any_supported_type(&key).map_err(|_| {
+        invalid_data(format!(
+            "unsupported private key in '{}'",
+            config.key.display()
+        ))
+    })?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(signing_key)))
+}
+
+pub fn server_config(resolver: Arc<CertResolver>) -> ServerConfig {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = resolver;
+    config
+}
+
+/// Reloads the certificates whenever the process receives SIGHUP.
+#[cfg(unix)]
+pub fn reload_on_sighup(resolver: Arc<CertResolver>) {
+    use actix_rt::signal::unix::{signal, SignalKind};
+
+    actix_rt::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(error) => {
+                error!(%error, "can't listen for SIGHUP");
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            match resolver.reload() {
+                Ok(()) => info!("TLS certificates reloaded"),
+                Err(error) => error!(%error, "TLS certificates reload failed"),
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn reload_on_sighup(_: Arc<CertResolver>) {}
+
+// ---- Redirect ----
+
+/// Response redirecting a plain HTTP request to the same URL on `https_port`.
+pub fn https_redirect(req: ServiceRequest, https_port: u16) -> ServiceResponse {
+    let host = req.connection_info().host().to_owned();
+    // Strip the port, but not the last segment of a bare IPv6 address.
+    let host = match host.rfind(':') {
+        Some(index) if !host.ends_with(']') => host[..index].to_owned(),
+        _ => host,
+    };
+    let authority = match https_port {
+        443 => host,
+        port => format!("{}:{}", host, port),
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |path_and_query| path_and_query.as_str())
+        .to_owned();
+
+    req.into_response(
+        HttpResponse::PermanentRedirect()
+            .header(header::LOCATION, format!("https://{}{}", authority, path))
+            .finish(),
+    )
+}
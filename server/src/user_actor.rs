@@ -0,0 +1,299 @@
+use actix::prelude::*;
+use actix_web::{http::StatusCode, ResponseError};
+use std::fmt;
+use std::io;
+use std::time::Duration;
+use tracing::error;
+
+use crate::user_store::{User, UserStore, Users};
+
+// Older messages are dropped from the history.
+const MAX_HISTORY_LEN: usize = 100;
+
+const USERNAME_LEN: std::ops::RangeInclusive<usize> = 3..=32;
+const MIN_PASSWORD_LEN: usize = 8;
+
+// ---- Actor ----
+
+/// Keeps the user accounts and their message history.
+/// New accounts are saved into the store right away,
+/// recorded messages every `save_interval`.
+pub struct UserActor {
+    users: Users,
+    store: UserStore,
+    save_interval: Duration,
+    // Messages were recorded since the last save.
+    unsaved: bool,
+}
+
+impl UserActor {
+    pub fn new(store: UserStore, save_interval: Duration) -> io::Result<Self> {
+        Ok(Self {
+            users: store.load()?,
+            store,
+            save_interval,
+            unsaved: false,
+        })
+    }
+
+    fn save_messages(&mut self) -> io::Result<()> {
+        if !self.unsaved {
+            return Ok(());
+        }
+        self.store.save(&self.users)?;
+        self.unsaved = false;
+        Ok(())
+    }
+}
+
+impl Actor for UserActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_interval(self.save_interval, |actor, _| {
+            if let Err(error) = actor.save_messages() {
+                error!(%error, "UserActor save failed");
+            }
+        });
+    }
+
+    fn stopping(&mut self, _: &mut Context<Self>) -> Running {
+        if let Err(error) = self.save_messages() {
+            error!(%error, "UserActor final save failed");
+        }
+        Running::Stop
+    }
+}
+
+// ---- Errors ----
+
+#[derive(Debug)]
+pub enum UserError {
+    InvalidUsername(String),
+    WeakPassword,
+    UsernameTaken(String),
+    InvalidCredentials,
+    Hashing(String),
+    Store(io::Error),
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidUsername(username) => write!(
+                f,
+                "Invalid username '{}', use {} to {} letters, digits, '-', '_' and '.'",
+                username,
+                USERNAME_LEN.start(),
+                USERNAME_LEN.end()
+            ),
+            Self::WeakPassword => write!(
+                f,
+                "Password has to be at least {} characters long",
+                MIN_PASSWORD_LEN
+            ),
+            Self::UsernameTaken(username) => write!(f, "Username '{}' is taken", username),
+            Self::InvalidCredentials => write!(f, "Invalid username or password"),
+            Self::Hashing(reason) => write!(f, "Password hashing failed: {}", reason),
+            Self::Store(error) => write!(f, "User store failed: {}", error),
+        }
+    }
+}
+
+impl ResponseError for UserError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::InvalidUsername(_) | Self::WeakPassword => StatusCode::BAD_REQUEST,
+            Self::UsernameTaken(_) => StatusCode::CONFLICT,
+            Self::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Self::Hashing(_) | Self::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<io::Error> for UserError {
+    fn from(error: io::Error) -> Self {
+        Self::Store(error)
+    }
+}
+
+pub fn validate_credentials(username: &str, password: &str) -> Result<(), UserError> {
+    let valid_username = USERNAME_LEN.contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid_username {
+        return Err(UserError::InvalidUsername(username.to_owned()));
+    }
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err(UserError::WeakPassword);
+    }
+    Ok(())
+}
+
+// ---- Messages ----
+
+/// Creates an account, the password has to be hashed already.
+pub struct MsgRegister {
+    pub username: String,
+    pub password_hash: String,
+}
+
+impl Message for MsgRegister {
+    type Result = Result<(), UserError>;
+}
+
+/// Returns the password hash to verify a login against, `None` for unknown users.
+pub struct MsgPasswordHash {
+    pub username: String,
+}
+
+impl Message for MsgPasswordHash {
+    type Result = Option<String>;
+}
+
+/// Adds the message to the history of the user, saved with the next interval.
+pub struct MsgRecordMessage {
+    pub username: String,
+    pub message: shared::SendMessageResponseBody,
+}
+
+impl Message for MsgRecordMessage {
+    type Result = ();
+}
+
+/// Saves the recorded messages, e.g. before shutdown.
+pub struct MsgFlush;
+
+impl Message for MsgFlush {
+    type Result = io::Result<()>;
+}
+
+pub struct MsgHistory {
+    pub username: String,
+}
+
+impl Message for MsgHistory {
+    type Result = Vec<shared::SendMessageResponseBody>;
+}
+
+// ---- Handlers ----
+
+impl Handler<MsgRegister> for UserActor {
+    type Result = Result<(), UserError>;
+
+    fn handle(&mut self, msg: MsgRegister, _: &mut Context<Self>) -> Self::Result {
+        if self.users.contains_key(&msg.username) {
+            return Err(UserError::UsernameTaken(msg.username));
+        }
+        let user = User {
+            password_hash: msg.password_hash,
+            messages: Vec::new(),
+        };
+        self.users.insert(msg.username.clone(), user);
+        if let Err(error) = self.store.save(&self.users) {
+            self.users.remove(&msg.username);
+            return Err(error.into());
+        }
+        // Saved with the account.
+        self.unsaved = false;
+        Ok(())
+    }
+}
+
+impl Handler<MsgPasswordHash> for UserActor {
+    type Result = Option<String>;
+
+    fn handle(&mut self, msg: MsgPasswordHash, _: &mut Context<Self>) -> Self::Result {
+        self.users
+            .get(&msg.username)
+            .map(|user| user.password_hash.clone())
+    }
+}
+
+impl Handler<MsgRecordMessage> for UserActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MsgRecordMessage, _: &mut Context<Self>) -> Self::Result {
+        let user = match self.users.get_mut(&msg.username) {
+            Some(user) => user,
+            None => return,
+        };
+        user.messages.push(msg.message);
+        let overflow = user.messages.len().saturating_sub(MAX_HISTORY_LEN);
+        user.messages.drain(..overflow);
+        self.unsaved = true;
+    }
+}
+
+impl Handler<MsgFlush> for UserActor {
+    type Result = io::Result<()>;
+
+    fn handle(&mut self, _: MsgFlush, _: &mut Context<Self>) -> Self::Result {
+        self.save_messages()
+    }
+}
+
+impl Handler<MsgHistory> for UserActor {
+    type Result = MessageResult<MsgHistory>;
+
+    fn handle(&mut self, msg: MsgHistory, _: &mut Context<Self>) -> Self::Result {
+        MessageResult(
+            self.users
+                .get(&msg.username)
+                .map(|user| user.messages.clone())
+                .unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(ordinal_number: u64) -> shared::SendMessageResponseBody {
+        shared::SendMessageResponseBody {
+            ordinal_number,
+            text: format!("message {}", ordinal_number),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn messages_are_saved_on_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = UserStore::File(dir.path().join("users.json"));
+        let actor = UserActor::new(store.clone(), Duration::from_secs(600))
+            .unwrap()
+            .start();
+        let saved_messages = || store.load().unwrap()["alice"].messages.len();
+
+        actor
+            .send(MsgRegister {
+                username: "alice".to_owned(),
+                password_hash: "hash".to_owned(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        for ordinal_number in 1..=3 {
+            actor
+                .send(MsgRecordMessage {
+                    username: "alice".to_owned(),
+                    message: message(ordinal_number),
+                })
+                .await
+                .unwrap();
+        }
+        assert_eq!(saved_messages(), 0);
+
+        actor.send(MsgFlush).await.unwrap().unwrap();
+        assert_eq!(saved_messages(), 3);
+        let history = actor
+            .send(MsgHistory {
+                username: "alice".to_owned(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 3);
+    }
+}
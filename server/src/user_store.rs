@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::count_store::write_atomically;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    /// Argon2id hash in the PHC string format.
+    pub password_hash: String,
+    /// The latest messages sent through `/api/send-message`, oldest first.
+    #[serde(default)]
+    pub messages: Vec<shared::SendMessageResponseBody>,
+}
+
+/// Users by username.
+pub type Users = BTreeMap<String, User>;
+
+// ---- Store ----
+
+/// Durable backend for the `UserActor` accounts.
+#[derive(Debug, Clone)]
+pub enum UserStore {
+    /// Nothing is persisted, the accounts are gone on every restart.
+    Memory,
+    /// The accounts are kept in a JSON file, written atomically and fsynced.
+    File(PathBuf),
+}
+
+impl UserStore {
+    pub fn load(&self) -> io::Result<Users> {
+        match self {
+            Self::Memory => Ok(Users::new()),
+            Self::File(path) => match fs::read(path) {
+                Ok(content) => serde_json::from_slice(&content)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Users::new()),
+                Err(err) => Err(err),
+            },
+        }
+    }
+
+    pub fn save(&self, users: &Users) -> io::Result<()> {
+        match self {
+            Self::Memory => Ok(()),
+            Self::File(path) => write_atomically(path, &serde_json::to_vec_pretty(users)?),
+        }
+    }
+}
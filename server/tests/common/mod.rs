@@ -0,0 +1,80 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("bind a free port")
+        .port()
+}
+
+/// Starts the server with `config` as `server.toml` in `dir`
+/// and waits until it accepts connections on `port`.
+pub fn start_server(dir: &Path, config: &str, port: u16) -> Child {
+    let config_path = dir.join("server.toml");
+    fs::write(&config_path, config).expect("write config");
+
+    let server = Command::new(env!("CARGO_BIN_EXE_server"))
+        .arg("--config")
+        .arg(&config_path)
+        .current_dir(dir)
+        .env("LOG_LEVEL", "warn")
+        .spawn()
+        .expect("start server");
+
+    let started = Instant::now();
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(started.elapsed() < STARTUP_TIMEOUT, "server didn't start");
+        thread::sleep(Duration::from_millis(50));
+    }
+    server
+}
+
+/// Sends a plain HTTP request with a JSON `body` and returns the raw response.
+#[allow(dead_code)]
+pub fn send(port: u16, method: &str, path: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("connect");
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        body.len(),
+        body
+    )
+    .expect("send request");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("read response");
+    response
+}
+
+/// Self-signed certificate for `localhost`, written to `{name}.pem` and `{name}-key.pem`.
+#[allow(dead_code)]
+pub fn generate_cert(dir: &Path, name: &str) -> (PathBuf, PathBuf) {
+    let cert = dir.join(format!("{}.pem", name));
+    let key = dir.join(format!("{}-key.pem", name));
+    let status = Command::new("openssl")
+        .args([
+            "req", "-x509", "-newkey", "rsa:2048", "-nodes", "-days", "1",
+        ])
+        .args(["-subj", "/CN=localhost"])
+        .args(["-addext", "subjectAltName=DNS:localhost"])
+        .args(["-addext", "basicConstraints=critical,CA:FALSE"])
+        .arg("-keyout")
+        .arg(&key)
+        .arg("-out")
+        .arg(&cert)
+        .output()
+        .expect("run openssl")
+        .status;
+    assert!(status.success(), "openssl failed");
+    (cert, key)
+}
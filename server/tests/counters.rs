@@ -0,0 +1,47 @@
+mod common;
+
+use common::{free_port, send};
+
+#[test]
+fn increment_body_is_optional_but_has_to_be_valid() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let port = free_port();
+    let config = format!(
+        r#"
+bind_address = "127.0.0.1"
+port = {port}
+
+[uploads]
+dir = "{dir}/uploads"
+"#,
+        port = port,
+        dir = dir.path().display()
+    );
+    let mut server = common::start_server(dir.path(), &config, port);
+    let increment = |body| send(port, "POST", "/api/counters/clicks/increment", body);
+
+    let valid = [
+        ("", r#""value":1"#),
+        (r#"{"step": 5}"#, r#""value":6"#),
+        ("{}", r#""value":7"#),
+    ];
+    for (body, value) in &valid {
+        let response = increment(body);
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains(value), "{}", response);
+    }
+    for body in &[r#"{"step": -1}"#, r#"{"step": "2"}"#, "step=2"] {
+        let response = increment(body);
+        assert!(
+            response.starts_with("HTTP/1.1 400"),
+            "{}: {}",
+            body,
+            response
+        );
+    }
+    let response = send(port, "GET", "/api/counters/clicks", "");
+    assert!(response.contains(r#""value":7"#), "{}", response);
+
+    server.kill().expect("stop server");
+    server.wait().expect("wait for server");
+}
@@ -0,0 +1,66 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::fs;
+use std::path::Path;
+use std::process::{Child, Command};
+use std::thread;
+use std::time::Duration;
+
+use common::{free_port, send};
+
+fn start_server(dir: &Path, port: u16) -> Child {
+    let config = format!(
+        r#"
+bind_address = "127.0.0.1"
+port = {port}
+shutdown_timeout_secs = 10
+
+[uploads]
+dir = "{dir}/uploads"
+
+[count_store]
+backend = "file"
+path = "{dir}/count.txt"
+# Only the flush on shutdown writes the file.
+snapshot_interval_ms = 600000
+"#,
+        port = port,
+        dir = dir.display()
+    );
+    common::start_server(dir, &config, port)
+}
+
+#[test]
+fn graceful_shutdown_completes_requests_and_flushes_counters() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let port = free_port();
+    let mut server = start_server(dir.path(), port);
+
+    let response = send(port, "POST", "/api/counters/visits/increment", "");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let slow_request = thread::spawn(move || send(port, "GET", "/api/delayed-response/2000", ""));
+    // Let the slow request reach the handler before shutting down.
+    thread::sleep(Duration::from_millis(500));
+    let killed = Command::new("kill")
+        .arg("-TERM")
+        .arg(server.id().to_string())
+        .status()
+        .expect("send SIGTERM");
+    assert!(killed.success());
+
+    let response = slow_request.join().expect("slow request");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(
+        response.ends_with("Delay was set to 2000ms."),
+        "{}",
+        response
+    );
+
+    let status = server.wait().expect("wait for server");
+    assert!(status.success(), "server exited with {}", status);
+    let counts = fs::read_to_string(dir.path().join("count.txt")).expect("read counts");
+    assert!(counts.lines().any(|line| line == "visits 1"), "{}", counts);
+}
@@ -0,0 +1,184 @@
+mod common;
+
+use rustls::internal::pemfile;
+use rustls::{Certificate, ClientConfig, ClientSession, Session};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::process::Child;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{free_port, generate_cert};
+
+struct Server {
+    process: Child,
+    https_port: u16,
+    redirect_port: u16,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+    }
+}
+
+fn start_server(dir: &Path, cert: &Path, key: &Path) -> Server {
+    let https_port = free_port();
+    let redirect_port = free_port();
+    let config = format!(
+        r#"
+bind_address = "127.0.0.1"
+port = {https_port}
+
+[uploads]
+dir = "{dir}/uploads"
+
+[tls]
+cert = "{cert}"
+key = "{key}"
+redirect_port = {redirect_port}
+"#,
+        https_port = https_port,
+        redirect_port = redirect_port,
+        dir = dir.display(),
+        cert = cert.display(),
+        key = key.display()
+    );
+    let process = common::start_server(dir, &config, https_port);
+    Server {
+        process,
+        https_port,
+        redirect_port,
+    }
+}
+
+fn read_cert(path: &Path) -> Certificate {
+    let file = File::open(path).expect("open certificate");
+    pemfile::certs(&mut BufReader::new(file))
+        .expect("parse certificate")
+        .remove(0)
+}
+
+// Trusts only `cert`.
+fn client_config(cert: &Path) -> Arc<ClientConfig> {
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add(&read_cert(cert))
+        .expect("add root certificate");
+    Arc::new(config)
+}
+
+// The response to `GET path` and the certificate the server presented.
+fn https_get(
+    port: u16,
+    config: &Arc<ClientConfig>,
+    path: &str,
+) -> io::Result<(String, Certificate)> {
+    let dns_name = webpki::DNSNameRef::try_from_ascii_str("localhost").unwrap();
+    let mut session = ClientSession::new(config, dns_name);
+    let mut socket = TcpStream::connect(("127.0.0.1", port)).expect("connect");
+    let mut stream = rustls::Stream::new(&mut session, &mut socket);
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        path
+    );
+    stream.write_all(request.as_bytes())?;
+    let mut response = Vec::new();
+    match stream.read_to_end(&mut response) {
+        // The server may close the connection without `close_notify`.
+        Err(error) if error.kind() == io::ErrorKind::ConnectionAborted => {}
+        result => {
+            result?;
+        }
+    }
+    let certificate = session
+        .get_peer_certificates()
+        .and_then(|certs| certs.into_iter().next())
+        .expect("peer certificate");
+    Ok((String::from_utf8_lossy(&response).into_owned(), certificate))
+}
+
+#[test]
+fn serves_https_with_the_configured_certificate() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let (cert, key) = generate_cert(dir.path(), "cert");
+    let server = start_server(dir.path(), &cert, &key);
+
+    let (response, certificate) =
+        https_get(server.https_port, &client_config(&cert), "/healthz").expect("handshake");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert_eq!(certificate, read_cert(&cert));
+
+    // A client that doesn't trust the certificate fails the handshake.
+    let (other_cert, _) = generate_cert(dir.path(), "other");
+    assert!(https_get(server.https_port, &client_config(&other_cert), "/healthz").is_err());
+}
+
+#[test]
+fn redirects_http_to_https() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let (cert, key) = generate_cert(dir.path(), "cert");
+    let server = start_server(dir.path(), &cert, &key);
+
+    let mut stream = TcpStream::connect(("127.0.0.1", server.redirect_port)).expect("connect");
+    write!(
+        stream,
+        "GET /api/counter?x=1 HTTP/1.1\r\nHost: localhost:{}\r\nConnection: close\r\n\r\n",
+        server.redirect_port
+    )
+    .expect("send request");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("read response");
+
+    assert!(response.starts_with("HTTP/1.1 308"), "{}", response);
+    let location = format!(
+        "location: https://localhost:{}/api/counter?x=1\r\n",
+        server.https_port
+    );
+    assert!(response.to_lowercase().contains(&location), "{}", response);
+}
+
+#[cfg(unix)]
+#[test]
+fn sighup_reloads_the_certificate() {
+    let dir = tempfile::tempdir().expect("create temp dir");
+    let (cert, key) = generate_cert(dir.path(), "cert");
+    let server = start_server(dir.path(), &cert, &key);
+    let old_certificate = read_cert(&cert);
+
+    let (new_cert, new_key) = generate_cert(dir.path(), "new");
+    fs::copy(&new_cert, &cert).expect("replace certificate");
+    fs::copy(&new_key, &key).expect("replace key");
+    let hangup = std::process::Command::new("kill")
+        .arg("-HUP")
+        .arg(server.process.id().to_string())
+        .status()
+        .expect("send SIGHUP");
+    assert!(hangup.success());
+
+    let config = client_config(&cert);
+    let started = Instant::now();
+    loop {
+        match https_get(server.https_port, &config, "/healthz") {
+            Ok((_, certificate)) => {
+                assert_ne!(certificate, old_certificate);
+                assert_eq!(certificate, read_cert(&new_cert));
+                break;
+            }
+            // Still the old certificate, not trusted by `config`.
+            Err(_) => {
+                assert!(
+                    started.elapsed() < Duration::from_secs(10),
+                    "certificate not reloaded"
+                );
+                thread::sleep(Duration::from_millis(50));
+            }
+        }
+    }
+}
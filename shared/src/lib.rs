@@ -7,7 +7,7 @@ pub struct SendMessageRequestBody {
 
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SendMessageResponseBody {
-    pub ordinal_number: u32,
+    pub ordinal_number: u64,
     pub text: String,
 }
 
@@ -23,3 +23,145 @@ pub struct Quaternion {
 pub struct RotationMatrix {
     pub values: [f64; 9],
 }
+
+/// Radians, the rotations around the x, y and z axes applied in that order.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EulerAngles {
+    pub roll: f64,
+    pub pitch: f64,
+    pub yaw: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Counter {
+    pub name: String,
+    pub value: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncrementCounterRequestBody {
+    #[serde(default = "default_step")]
+    pub step: u64,
+}
+
+impl Default for IncrementCounterRequestBody {
+    fn default() -> Self {
+        Self {
+            step: default_step(),
+        }
+    }
+}
+
+const fn default_step() -> u64 {
+    1
+}
+
+/// Server-Sent Event of `/api/batch-conversion`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ConversionProgress {
+    Started { total: u32 },
+    Progress { done: u32, total: u32, percent: u8 },
+    Completed { quaternions: Vec<Quaternion> },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JobRequest {
+    Delay { delay_ms: u64 },
+    BatchConversion { count: u32, delay_ms: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum JobResult {
+    Text(String),
+    Quaternions(Vec<Quaternion>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+    Running { percent: u8 },
+    Completed { result: JobResult },
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobStatus {
+    pub id: u64,
+    pub state: JobState,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenRequestBody {
+    pub api_key: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenResponseBody {
+    pub token: String,
+    pub expires_in: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CredentialsRequestBody {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SessionResponseBody {
+    pub username: String,
+    /// Has to be sent in the `x-csrf-token` header with all non-`GET` requests.
+    pub csrf_token: String,
+}
+
+/// Upload of `/api/form`, downloadable from `/api/files/{id}`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StoredFile {
+    /// The hex encoded sha256 of the content, equal files have the same ID.
+    pub id: String,
+    pub name: String,
+    pub size: u64,
+    pub content_type: String,
+    pub sha256: String,
+    /// Unix timestamp in seconds.
+    pub uploaded_at: u64,
+}
+
+/// Echo of a `/api/form` submission, fields in the order they were sent.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FormSubmission {
+    pub fields: Vec<FormField>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FormField {
+    pub name: String,
+    pub value: FormValue,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", content = "value", rename_all = "snake_case")]
+pub enum FormValue {
+    Text(String),
+    /// Text fields declared as checkboxes by the `checkboxes` query parameter.
+    Bool(bool),
+    File(StoredFile),
+}
+
+/// A rejected row of a file imported by `/api/matrix/import`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RowError {
+    /// 1-based, the line for CSV files and the matrix for JSON and `.npy` files.
+    pub row: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MatrixImportErrorResponseBody {
+    /// The first rejected rows.
+    pub errors: Vec<RowError>,
+    pub invalid_rows: usize,
+}